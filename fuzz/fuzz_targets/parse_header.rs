@@ -0,0 +1,38 @@
+#![no_main]
+
+use img2raw::{Endianness, Header, RawImage};
+use libfuzzer_sys::fuzz_target;
+use std::mem::size_of;
+
+// Headers are read from untrusted files, so none of the parsing functions nor
+// the accessors on the values they return may panic on arbitrary bytes.
+fuzz_target!(|bytes: &[u8]| {
+    for &endianness in &[Endianness::Little, Endianness::Big] {
+        if let Some(header) = Header::from_bytes_endian(bytes, endianness) {
+            let _ = header.pixel_count();
+            let _ = header.data_size();
+            let _ = header.layer_count();
+            let _ = header.to_string();
+        }
+    }
+
+    if let Ok((_, rest)) = Header::parse_slice(bytes) {
+        assert_eq!(rest.len(), bytes.len() - size_of::<Header>());
+    }
+
+    if let Ok(image) = RawImage::parse(bytes) {
+        let layer_count = image.header().layer_count();
+
+        for index in 0..layer_count.min(4) {
+            assert!(image.layer(index).is_some());
+        }
+
+        assert!(image.layer(layer_count).is_none());
+
+        if let Some(metadata) = image.metadata() {
+            for (key, _) in metadata.iter() {
+                assert!(metadata.get(key).is_some());
+            }
+        }
+    }
+});
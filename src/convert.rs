@@ -0,0 +1,78 @@
+use crate::ColorSpace;
+use image::DynamicImage;
+use std::vec::Vec;
+
+/// Converts an image into RGBA pixels suitable for encoding.
+///
+/// The pixels are returned in scanline order with each channel normalized to
+/// the 0..1 range. The transfer function of the source color space is decoded
+/// so that `SRGB` and `AdobeRGB` pixels are returned in linear light, whereas
+/// pixels in any other color space are returned as-is. Alpha is unchanged.
+pub fn from_dynamic_image(image: &DynamicImage, source_color_space: ColorSpace) -> Vec<[f32; 4]> {
+    let decode: fn(f32) -> f32 = match source_color_space {
+        ColorSpace::SRGB => decode_srgb,
+        ColorSpace::AdobeRGB => decode_adobe_rgb,
+        _ => |x| x,
+    };
+
+    image
+        .to_rgba()
+        .pixels()
+        .map(|pixel| {
+            [
+                decode(pixel.0[0] as f32 / 255.0),
+                decode(pixel.0[1] as f32 / 255.0),
+                decode(pixel.0[2] as f32 / 255.0),
+                pixel.0[3] as f32 / 255.0,
+            ]
+        })
+        .collect()
+}
+
+fn decode_srgb(x: f32) -> f32 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn decode_adobe_rgb(x: f32) -> f32 {
+    x.powf(563.0 / 256.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn sample_image() -> DynamicImage {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([255, 128, 0, 128]));
+        image.put_pixel(1, 0, Rgba([10, 64, 192, 255]));
+        DynamicImage::ImageRgba8(image)
+    }
+
+    #[test]
+    fn non_color_pixels_are_normalized() {
+        let pixels = from_dynamic_image(&sample_image(), ColorSpace::NonColor);
+
+        assert_eq!(pixels[0], [1.0, 128.0 / 255.0, 0.0, 128.0 / 255.0]);
+        assert_eq!(pixels[1], [10.0 / 255.0, 64.0 / 255.0, 192.0 / 255.0, 1.0]);
+    }
+
+    #[test]
+    fn srgb_pixels_are_linearized() {
+        let pixels = from_dynamic_image(&sample_image(), ColorSpace::SRGB);
+        let expected = [
+            [1.0, 0.215_861, 0.0, 0.501_961],
+            [0.003_035, 0.051_269, 0.527_115, 1.0],
+        ];
+
+        for (pixel, expected) in pixels.iter().zip(&expected) {
+            for (x, y) in pixel.iter().zip(expected) {
+                assert!((x - y).abs() < 1e-5, "{:?} != {:?}", pixel, expected);
+            }
+        }
+    }
+}
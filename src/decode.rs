@@ -0,0 +1,371 @@
+use crate::{DataFormat, HeaderBuilder};
+use half::f16;
+use std::io::{Error, ErrorKind, Result};
+use std::vec;
+use std::vec::Vec;
+
+/// Decodes pixel data from the given data format.
+///
+/// The pixels are returned as RGBA values in scanline order with row padding
+/// removed. Channels not present in the data format are set to zero, except
+/// for alpha which is set to one. Multi-byte values are read little-endian.
+/// Fails if there are fewer bytes than needed.
+pub fn decode_pixels(
+    data_format: DataFormat,
+    width: u32,
+    height: u32,
+    bytes: &[u8],
+) -> Result<Vec<[f32; 4]>> {
+    let header = HeaderBuilder::new()
+        .data_format(data_format)
+        .dimensions(width, height)
+        .build();
+
+    let data_size = header
+        .data_size()
+        .filter(|&size| size <= bytes.len() as u64);
+
+    let data_size = match data_size {
+        Some(data_size) => data_size as usize,
+        None => {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "pixel data is shorter than expected",
+            ))
+        }
+    };
+
+    if width == 0 || height == 0 {
+        return Ok(Vec::new());
+    }
+
+    let (width, height) = (width as usize, height as usize);
+
+    let bytes_per_pixel = match (data_format.bytes_per_pixel(), data_format.bits_per_pixel()) {
+        (Some(bytes_per_pixel), _) => bytes_per_pixel,
+        (None, Some(bits_per_pixel)) => {
+            return Ok(decode_packed(
+                width,
+                height,
+                bits_per_pixel,
+                &bytes[..data_size],
+            ))
+        }
+        (None, None) => return Ok(decode_bc1(width, height, bytes)),
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+
+    for row in bytes[..data_size].chunks_exact(data_size / height) {
+        for pixel in row[..width * bytes_per_pixel].chunks_exact(bytes_per_pixel) {
+            pixels.push(decode_pixel(data_format, pixel));
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn decode_pixel(data_format: DataFormat, bytes: &[u8]) -> [f32; 4] {
+    match data_format {
+        DataFormat::R32F => [f32_at(bytes, 0), 0.0, 0.0, 1.0],
+        DataFormat::RG32F => [f32_at(bytes, 0), f32_at(bytes, 1), 0.0, 1.0],
+        DataFormat::RGBA32F => [
+            f32_at(bytes, 0),
+            f32_at(bytes, 1),
+            f32_at(bytes, 2),
+            f32_at(bytes, 3),
+        ],
+        DataFormat::R8 => [unorm8(bytes[0]), 0.0, 0.0, 1.0],
+        #[cfg(feature = "packed")]
+        DataFormat::PackedR8 => [unorm8(bytes[0]), 0.0, 0.0, 1.0],
+        DataFormat::R16F => [f16_at(bytes, 0), 0.0, 0.0, 1.0],
+        #[cfg(feature = "packed")]
+        DataFormat::PackedR16F => [f16_at(bytes, 0), 0.0, 0.0, 1.0],
+        DataFormat::RG16F => [f16_at(bytes, 0), f16_at(bytes, 1), 0.0, 1.0],
+        DataFormat::RGBA16F => [
+            f16_at(bytes, 0),
+            f16_at(bytes, 1),
+            f16_at(bytes, 2),
+            f16_at(bytes, 3),
+        ],
+        #[cfg(feature = "compressed")]
+        DataFormat::RGBE8 => rgbe8(bytes),
+        #[cfg(feature = "compressed")]
+        DataFormat::RGB9E5 => rgb9e5(bytes),
+        #[cfg(feature = "compressed")]
+        DataFormat::R11G11B10F => r11g11b10f(bytes),
+        DataFormat::RGBA8 | DataFormat::SRGBA8 => [
+            unorm8(bytes[0]),
+            unorm8(bytes[1]),
+            unorm8(bytes[2]),
+            unorm8(bytes[3]),
+        ],
+        #[cfg(feature = "compressed")]
+        DataFormat::BC1 => unreachable!(),
+        #[cfg(feature = "packed")]
+        DataFormat::PackedR1 | DataFormat::PackedR4 => unreachable!(),
+        DataFormat::RG8 => [unorm8(bytes[0]), unorm8(bytes[1]), 0.0, 1.0],
+        DataFormat::RGBA8Snorm => [
+            snorm8(bytes[0]),
+            snorm8(bytes[1]),
+            snorm8(bytes[2]),
+            snorm8(bytes[3]),
+        ],
+        DataFormat::RG8Snorm => [snorm8(bytes[0]), snorm8(bytes[1]), 0.0, 1.0],
+        DataFormat::R32U => [u32_at(bytes, 0), 0.0, 0.0, 1.0],
+        DataFormat::RGBA32U => [
+            u32_at(bytes, 0),
+            u32_at(bytes, 1),
+            u32_at(bytes, 2),
+            u32_at(bytes, 3),
+        ],
+    }
+}
+
+fn f32_at(bytes: &[u8], index: usize) -> f32 {
+    let bytes = &bytes[4 * index..4 * index + 4];
+    f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn f16_at(bytes: &[u8], index: usize) -> f32 {
+    let bytes = &bytes[2 * index..2 * index + 2];
+    f16::from_bits(u16::from_le_bytes([bytes[0], bytes[1]])).to_f32()
+}
+
+// Values above 2^24 are not exactly representable and are rounded.
+fn u32_at(bytes: &[u8], index: usize) -> f32 {
+    let bytes = &bytes[4 * index..4 * index + 4];
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+}
+
+fn unorm8(x: u8) -> f32 {
+    x as f32 / 255.0
+}
+
+fn snorm8(x: u8) -> f32 {
+    x as i8 as f32 / 128.0
+}
+
+#[cfg(feature = "compressed")]
+fn rgbe8(bytes: &[u8]) -> [f32; 4] {
+    if bytes[3] == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+
+    let scale = 2f32.powi(bytes[3] as i32 - 136);
+
+    [
+        (bytes[0] as f32 + 0.5) * scale,
+        (bytes[1] as f32 + 0.5) * scale,
+        (bytes[2] as f32 + 0.5) * scale,
+        1.0,
+    ]
+}
+
+// Pixels are packed MSB-first, with each row starting on a byte boundary.
+fn decode_packed(
+    width: usize,
+    height: usize,
+    bits_per_pixel: usize,
+    bytes: &[u8],
+) -> Vec<[f32; 4]> {
+    let mask = ((1 << bits_per_pixel) - 1) as u8;
+
+    let mut pixels = Vec::with_capacity(width * height);
+
+    for row in bytes.chunks_exact(bytes.len() / height) {
+        for x in 0..width {
+            let bit = x * bits_per_pixel;
+            let level = row[bit / 8] >> (8 - bits_per_pixel - bit % 8) & mask;
+
+            pixels.push([level as f32 / mask as f32, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    pixels
+}
+
+fn decode_bc1(width: usize, height: usize, bytes: &[u8]) -> Vec<[f32; 4]> {
+    let mut pixels = vec![[0.0; 4]; width * height];
+
+    let blocks_per_row = width.div_ceil(4);
+
+    for (index, block) in bytes.chunks_exact(8).enumerate() {
+        let (block_x, block_y) = (index % blocks_per_row * 4, index / blocks_per_row * 4);
+
+        if block_y >= height {
+            break;
+        }
+
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+        let (p0, p1) = (rgb565(c0), rgb565(c1));
+        let mut palette = [p0, p1, [0.0, 0.0, 0.0, 1.0], [0.0, 0.0, 0.0, 1.0]];
+
+        for channel in 0..3 {
+            if c0 > c1 {
+                palette[2][channel] = (2.0 * p0[channel] + p1[channel]) / 3.0;
+                palette[3][channel] = (p0[channel] + 2.0 * p1[channel]) / 3.0;
+            } else {
+                palette[2][channel] = (p0[channel] + p1[channel]) / 2.0;
+            }
+        }
+
+        for y in 0..4 {
+            for x in 0..4 {
+                if block_x + x < width && block_y + y < height {
+                    let index = (indices >> (2 * (4 * y + x))) & 3;
+                    pixels[(block_y + y) * width + block_x + x] = palette[index as usize];
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+fn rgb565(color: u16) -> [f32; 4] {
+    [
+        (color >> 11) as f32 / 31.0,
+        ((color >> 5) & 0x3f) as f32 / 63.0,
+        (color & 0x1f) as f32 / 31.0,
+        1.0,
+    ]
+}
+
+#[cfg(feature = "compressed")]
+fn rgb9e5(bytes: &[u8]) -> [f32; 4] {
+    let packed = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let scale = 2f32.powi((packed >> 27) as i32 - 24);
+
+    [
+        (packed & 0x1ff) as f32 * scale,
+        (packed >> 9 & 0x1ff) as f32 * scale,
+        (packed >> 18 & 0x1ff) as f32 * scale,
+        1.0,
+    ]
+}
+
+#[cfg(feature = "compressed")]
+fn r11g11b10f(bytes: &[u8]) -> [f32; 4] {
+    let packed = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    [
+        small_float(packed & 0x7ff, 6),
+        small_float(packed >> 11 & 0x7ff, 6),
+        small_float(packed >> 22, 5),
+        1.0,
+    ]
+}
+
+#[cfg(feature = "compressed")]
+fn small_float(bits: u32, mantissa_bits: i32) -> f32 {
+    let exponent = (bits >> mantissa_bits) as i32;
+    let mantissa = (bits & ((1 << mantissa_bits) - 1)) as f32;
+
+    match exponent {
+        0 => mantissa * 2f32.powi(-14 - mantissa_bits),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (mantissa * 2f32.powi(-mantissa_bits) + 1.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(all(test, feature = "encode"))]
+mod tests {
+    use super::*;
+    use crate::encode_pixels;
+
+    const WIDTH: u32 = 4;
+    const HEIGHT: u32 = 2;
+
+    fn sample_pixels() -> Vec<[f32; 4]> {
+        let mut pixels = Vec::new();
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let x = x as f32 / (WIDTH - 1) as f32;
+                let y = y as f32 / (HEIGHT - 1) as f32;
+
+                pixels.push([x, y, 1.0 - x, (x + y) / 2.0]);
+            }
+        }
+
+        pixels
+    }
+
+    // One quantization step for fixed-point formats, and a bound on the rounding
+    // error for floating-point formats, for values in [0, 1].
+    fn tolerance(data_format: DataFormat) -> f32 {
+        match data_format {
+            DataFormat::R32F | DataFormat::RG32F | DataFormat::RGBA32F => 0.0,
+            DataFormat::R16F | DataFormat::RG16F | DataFormat::RGBA16F => 1e-3,
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR16F => 1e-3,
+            DataFormat::R8 | DataFormat::RG8 | DataFormat::RGBA8 | DataFormat::SRGBA8 => {
+                1.0 / 255.0
+            }
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR8 => 1.0 / 255.0,
+            DataFormat::RG8Snorm | DataFormat::RGBA8Snorm => 1.0 / 128.0,
+            DataFormat::R32U | DataFormat::RGBA32U => 1.0,
+            #[cfg(feature = "compressed")]
+            DataFormat::RGBE8 | DataFormat::RGB9E5 => 1.0 / 128.0,
+            #[cfg(feature = "compressed")]
+            DataFormat::R11G11B10F => 1.0 / 32.0,
+            #[cfg(feature = "compressed")]
+            DataFormat::BC1 => unreachable!(),
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR1 => 0.5,
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR4 => 1.0 / 15.0,
+        }
+    }
+
+    #[test]
+    fn decoded_pixels_match_encoded_pixels() {
+        let pixels = sample_pixels();
+
+        for &data_format in DataFormat::all() {
+            if data_format.bits_per_pixel().is_none() {
+                continue;
+            }
+
+            let mut bytes = Vec::new();
+            encode_pixels(&mut bytes, data_format, WIDTH, &pixels).unwrap();
+
+            let decoded = decode_pixels(data_format, WIDTH, HEIGHT, &bytes).unwrap();
+            assert_eq!(decoded.len(), pixels.len());
+
+            let channels = data_format.channel_count();
+
+            for (actual, expected) in decoded.iter().zip(&pixels) {
+                for channel in 0..4 {
+                    let expected = match channel {
+                        _ if channel < channels => expected[channel],
+                        3 => 1.0,
+                        _ => 0.0,
+                    };
+
+                    let error = (actual[channel] - expected).abs();
+
+                    assert!(
+                        error <= tolerance(data_format),
+                        "{}: {:?} != {:?}",
+                        data_format,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_pixel_data_is_rejected() {
+        let bytes = [0; 31];
+        assert!(decode_pixels(DataFormat::RGBA8, WIDTH, HEIGHT, &bytes).is_err());
+    }
+}
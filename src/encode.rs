@@ -0,0 +1,263 @@
+use crate::DataFormat;
+use half::f16;
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// Encodes pixel data into the given data format.
+///
+/// The pixels are RGBA values in scanline order, and the number of pixels must
+/// be a multiple of the image width. Each row is padded according to the data
+/// format's row alignment, and multi-byte values are written little-endian.
+/// No color conversion is done, so the sRGB formats expect sRGB-encoded color.
+/// Block-compressed formats are not supported.
+pub fn encode_pixels<W: Write>(
+    mut writer: W,
+    data_format: DataFormat,
+    width: u32,
+    pixels: &[[f32; 4]],
+) -> Result<()> {
+    #[cfg(feature = "compressed")]
+    if data_format == DataFormat::BC1 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "block-compressed formats are not supported",
+        ));
+    }
+
+    if width == 0 || !pixels.len().is_multiple_of(width as usize) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "pixel count is not a multiple of the width",
+        ));
+    }
+
+    for row in pixels.chunks(width as usize) {
+        encode_row(&mut writer, data_format, row)?;
+    }
+
+    Ok(())
+}
+
+fn encode_row<W: Write>(writer: &mut W, data_format: DataFormat, row: &[[f32; 4]]) -> Result<()> {
+    if let (None, Some(bits_per_pixel)) =
+        (data_format.bytes_per_pixel(), data_format.bits_per_pixel())
+    {
+        return encode_packed_row(writer, bits_per_pixel, row);
+    }
+
+    for &[r, g, b, a] in row {
+        match data_format {
+            DataFormat::R32F => write_f32s(writer, &[r])?,
+            DataFormat::RG32F => write_f32s(writer, &[r, g])?,
+            DataFormat::RGBA32F => write_f32s(writer, &[r, g, b, a])?,
+            DataFormat::R8 => writer.write_all(&[unorm8(r)])?,
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR8 => writer.write_all(&[unorm8(r)])?,
+            DataFormat::R16F => write_f16s(writer, &[r])?,
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR16F => write_f16s(writer, &[r])?,
+            DataFormat::RG16F => write_f16s(writer, &[r, g])?,
+            DataFormat::RGBA16F => write_f16s(writer, &[r, g, b, a])?,
+            #[cfg(feature = "compressed")]
+            DataFormat::RGBE8 => writer.write_all(&rgbe8(r, g, b))?,
+            DataFormat::RGBA8 | DataFormat::SRGBA8 => {
+                writer.write_all(&[unorm8(r), unorm8(g), unorm8(b), unorm8(a)])?
+            }
+            #[cfg(feature = "compressed")]
+            DataFormat::BC1 => unreachable!(),
+            #[cfg(feature = "packed")]
+            DataFormat::PackedR1 | DataFormat::PackedR4 => unreachable!(),
+            DataFormat::RG8 => writer.write_all(&[unorm8(r), unorm8(g)])?,
+            DataFormat::RGBA8Snorm => {
+                writer.write_all(&[snorm8(r), snorm8(g), snorm8(b), snorm8(a)])?
+            }
+            DataFormat::RG8Snorm => writer.write_all(&[snorm8(r), snorm8(g)])?,
+            DataFormat::R32U => write_u32s(writer, &[r])?,
+            DataFormat::RGBA32U => write_u32s(writer, &[r, g, b, a])?,
+            #[cfg(feature = "compressed")]
+            DataFormat::RGB9E5 => writer.write_all(&rgb9e5(r, g, b).to_le_bytes())?,
+            #[cfg(feature = "compressed")]
+            DataFormat::R11G11B10F => writer.write_all(&r11g11b10f(r, g, b).to_le_bytes())?,
+        }
+    }
+
+    let row_padding = match data_format {
+        DataFormat::R8 => (4 - row.len() % 4) % 4,
+        DataFormat::R16F | DataFormat::RG8 | DataFormat::RG8Snorm => (row.len() % 2) * 2,
+        _ => 0,
+    };
+
+    writer.write_all(&[0; 3][..row_padding])
+}
+
+// Pixels are packed MSB-first and quantized to the nearest level with ties to
+// even, except that the 1-bit format is thresholded at 0.5 like the tool does,
+// and the last byte of the row is padded.
+fn encode_packed_row<W: Write>(
+    writer: &mut W,
+    bits_per_pixel: usize,
+    row: &[[f32; 4]],
+) -> Result<()> {
+    let max = ((1 << bits_per_pixel) - 1) as f32;
+
+    for pixels in row.chunks(8 / bits_per_pixel) {
+        let mut byte = 0;
+
+        for (index, &[r, ..]) in pixels.iter().enumerate() {
+            let level = if bits_per_pixel == 1 {
+                (r >= 0.5) as u8
+            } else {
+                (r.clamp(0.0, 1.0) * max).round_ties_even() as u8
+            };
+
+            byte |= level << (8 - bits_per_pixel * (index + 1));
+        }
+
+        writer.write_all(&[byte])?;
+    }
+
+    Ok(())
+}
+
+fn write_f32s<W: Write>(writer: &mut W, values: &[f32]) -> Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn write_f16s<W: Write>(writer: &mut W, values: &[f32]) -> Result<()> {
+    for value in values {
+        let value = f16::from_f32(value.clamp(-65504.0, 65504.0));
+        writer.write_all(&value.to_bits().to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+// Values are truncated towards zero and saturate to the range of a u32.
+fn write_u32s<W: Write>(writer: &mut W, values: &[f32]) -> Result<()> {
+    for value in values {
+        writer.write_all(&(*value as u32).to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+// Normalized values are rounded to nearest with ties to even, which is also
+// the default rounding mode of the tool.
+fn unorm8(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round_ties_even() as u8
+}
+
+fn snorm8(x: f32) -> u8 {
+    (x.clamp(-1.0, 1.0) * 128.0)
+        .round_ties_even()
+        .clamp(-128.0, 127.0) as i8 as u8
+}
+
+#[cfg(feature = "compressed")]
+fn rgbe8(r: f32, g: f32, b: f32) -> [u8; 4] {
+    let v = r.max(g).max(b);
+
+    if v < 1e-32 {
+        return [0; 4];
+    }
+
+    let (f, e) = frexp(v);
+
+    [
+        (r * f * 256.0 / v).clamp(0.0, 255.0) as u8,
+        (g * f * 256.0 / v).clamp(0.0, 255.0) as u8,
+        (b * f * 256.0 / v).clamp(0.0, 255.0) as u8,
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+// Packs the channels as described by the EXT_texture_shared_exponent spec.
+#[cfg(feature = "compressed")]
+fn rgb9e5(r: f32, g: f32, b: f32) -> u32 {
+    const MAX: f32 = 65408.0;
+
+    let clamp = |x: f32| if x.is_nan() { 0.0 } else { x.clamp(0.0, MAX) };
+    let (r, g, b) = (clamp(r), clamp(g), clamp(b));
+
+    let max = r.max(g).max(b);
+    let mut exponent = max.log2().floor().max(-16.0) as i32 + 16;
+
+    if (max / 2f32.powi(exponent - 24) + 0.5).floor() == 512.0 {
+        exponent += 1;
+    }
+
+    let scale = 2f32.powi(exponent - 24);
+    let mantissa = |x: f32| (x / scale + 0.5).floor() as u32;
+
+    mantissa(r) | mantissa(g) << 9 | mantissa(b) << 18 | (exponent as u32) << 27
+}
+
+// Packs the channels as unsigned floats with a 5-bit exponent biased by 15 and
+// 6-bit mantissas for red and green, or a 5-bit mantissa for blue.
+#[cfg(feature = "compressed")]
+fn r11g11b10f(r: f32, g: f32, b: f32) -> u32 {
+    small_float(r, 6) | small_float(g, 6) << 11 | small_float(b, 5) << 22
+}
+
+// Rounding up to the next power of two carries into the exponent as expected.
+#[cfg(feature = "compressed")]
+fn small_float(x: f32, mantissa_bits: i32) -> u32 {
+    let max = (2.0 - 2f32.powi(-mantissa_bits)) * 2f32.powi(15);
+    let x = if x.is_nan() { 0.0 } else { x.clamp(0.0, max) };
+
+    let exponent = x.log2().floor().max(-14.0) as i32;
+    let mantissa = (x / 2f32.powi(exponent - mantissa_bits) + 0.5).floor() as u32;
+
+    (((exponent + 15) as u32) << mantissa_bits) + mantissa - (1 << mantissa_bits)
+}
+
+// Returns (f, e) such that x = f * 2^e with 0.5 <= |f| < 1, like C's frexp.
+#[cfg(feature = "compressed")]
+fn frexp(x: f32) -> (f32, i32) {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32;
+
+    if exponent == 0 {
+        if x == 0.0 {
+            return (x, 0);
+        }
+
+        let (f, e) = frexp(x * 2f32.powi(64));
+        return (f, e - 64);
+    }
+
+    if exponent == 0xff {
+        return (x, 0);
+    }
+
+    let mantissa = f32::from_bits((bits & !(0xff << 23)) | (126 << 23));
+
+    (mantissa, exponent - 126)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_values_round_ties_to_even() {
+        assert_eq!(unorm8(0.5), 128);
+        assert_eq!(unorm8(0.5 / 255.0), 0);
+        assert_eq!(unorm8(1.5 / 255.0), 2);
+        assert_eq!(snorm8(-0.5) as i8, -64);
+        assert_eq!(snorm8(1.0) as i8, 127);
+    }
+
+    #[test]
+    #[cfg(feature = "packed")]
+    fn packed_r4_rounds_ties_to_even() {
+        let pixels = [[0.5, 0.0, 0.0, 1.0], [1.0, 0.0, 0.0, 1.0]];
+
+        let mut bytes = std::vec::Vec::new();
+        encode_pixels(&mut bytes, DataFormat::PackedR4, 2, &pixels).unwrap();
+        assert_eq!(bytes, [0x8f]);
+    }
+}
@@ -4,14 +4,37 @@
 #![forbid(unsafe_code)]
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "image")]
+mod convert;
+#[cfg(feature = "decode")]
+mod decode;
+#[cfg(feature = "encode")]
+mod encode;
+
+#[cfg(feature = "image")]
+pub use convert::from_dynamic_image;
+#[cfg(feature = "decode")]
+pub use decode::decode_pixels;
+#[cfg(feature = "encode")]
+pub use encode::encode_pixels;
+
+use core::convert::TryFrom;
 use core::fmt::{Display, Formatter, Result as FmtResult};
+use core::mem::{offset_of, size_of};
 use core::str::FromStr;
 #[cfg(feature = "zerocopy")]
 use zerocopy::{AsBytes, FromBytes};
 
 /// Header optionally prepended to the pixel data.
+///
+/// The default header is valid, with a `NonColor` color space, an `R32F` data
+/// format and zero dimensions. The provenance fields are left zeroed unless
+/// they are filled in by the tool that created the file.
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "zerocopy", derive(AsBytes, FromBytes))]
 pub struct Header {
     /// The color space of the subsequent pixel data.
@@ -20,6 +43,539 @@ pub struct Header {
     pub data_format: DataFormatInfo,
     /// The image width and height in pixels.
     pub dimensions: [u32; 2],
+    /// The CRC32 checksum of the subsequent pixel data.
+    pub checksum: u32,
+    /// Bit flags describing how the pixel data is stored.
+    pub flags: u32,
+    /// The version of the tool that created the file, or zero if unknown.
+    ///
+    /// This is encoded as `major << 16 | minor << 8 | patch`.
+    pub tool_version: u32,
+    /// The number of layers in the pixel data, e.g. for texture arrays.
+    ///
+    /// Zero is treated as a single layer, as in the default header.
+    pub layers: u32,
+    /// The creation time of the file in Unix seconds, or zero if unknown.
+    pub timestamp: u64,
+}
+
+// NOTE: the header is written to files as-is, so its layout must never change
+// by accident; update these assertions deliberately when adding new fields.
+
+const _: () = assert!(size_of::<Header>() == 40);
+const _: () = assert!(offset_of!(Header, color_space) == 0);
+const _: () = assert!(offset_of!(Header, data_format) == 4);
+const _: () = assert!(offset_of!(Header, dimensions) == 8);
+const _: () = assert!(offset_of!(Header, checksum) == 16);
+const _: () = assert!(offset_of!(Header, flags) == 20);
+const _: () = assert!(offset_of!(Header, tool_version) == 24);
+const _: () = assert!(offset_of!(Header, layers) == 28);
+const _: () = assert!(offset_of!(Header, timestamp) == 32);
+
+/// Byte order of the fields in a serialized header or of the pixel data.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Endianness {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl Header {
+    /// Flag set if multi-byte values in the pixel data are big-endian.
+    pub const FLAG_BIG_ENDIAN: u32 = 1 << 0;
+    /// Flag set if the color channels are premultiplied by the alpha channel.
+    pub const FLAG_PREMULTIPLIED_ALPHA: u32 = 1 << 1;
+    /// Flag set if the first row of the pixel data is the bottom of the image.
+    pub const FLAG_ORIGIN_BOTTOM_LEFT: u32 = 1 << 2;
+    /// Flag set if the pixel data is followed by the full mip chain.
+    pub const FLAG_MIPMAPPED: u32 = 1 << 3;
+    /// Flag set if the file ends with a metadata trailer.
+    pub const FLAG_METADATA: u32 = 1 << 4;
+    /// Bits of the flags holding the base-two logarithm of the row alignment
+    /// plus one if it overrides the data format's, or zero otherwise.
+    pub const ROW_ALIGNMENT_MASK: u32 = 0x3f << Self::ROW_ALIGNMENT_SHIFT;
+    /// Position of the row alignment bits in the flags.
+    pub const ROW_ALIGNMENT_SHIFT: u32 = 8;
+
+    /// Reads a little-endian header from the start of a byte slice.
+    ///
+    /// Returns `None` if the slice is shorter than the header.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::from_bytes_endian(bytes, Endianness::Little)
+    }
+
+    /// Reads a header with the given byte order from the start of a byte slice.
+    ///
+    /// Returns `None` if the slice is shorter than the header.
+    pub fn from_bytes_endian(bytes: &[u8], endianness: Endianness) -> Option<Self> {
+        let mut fields = [0; size_of::<Self>() / 4];
+
+        let bytes = bytes.get(..size_of::<Self>())?;
+
+        for (field, chunk) in fields.iter_mut().zip(bytes.chunks_exact(4)) {
+            let chunk = [chunk[0], chunk[1], chunk[2], chunk[3]];
+
+            *field = match endianness {
+                Endianness::Little => u32::from_le_bytes(chunk),
+                Endianness::Big => u32::from_be_bytes(chunk),
+            };
+        }
+
+        let timestamp = match endianness {
+            Endianness::Little => (fields[9] as u64) << 32 | fields[8] as u64,
+            Endianness::Big => (fields[8] as u64) << 32 | fields[9] as u64,
+        };
+
+        Some(Self {
+            color_space: ColorSpaceInfo(fields[0]),
+            data_format: DataFormatInfo(fields[1]),
+            dimensions: [fields[2], fields[3]],
+            checksum: fields[4],
+            flags: fields[5],
+            tool_version: fields[6],
+            layers: fields[7],
+            timestamp,
+        })
+    }
+
+    /// Reads a little-endian header from the start of a byte slice and checks
+    /// that its color space and data format are valid.
+    ///
+    /// Returns the header along with the bytes following it.
+    pub fn parse_slice(bytes: &[u8]) -> Result<(Self, &[u8]), HeaderError> {
+        let header = Self::from_bytes(bytes).ok_or(HeaderError::TooShort)?;
+
+        if header.color_space.try_parse().is_none() {
+            return Err(HeaderError::InvalidColorSpace);
+        }
+
+        if header.data_format.try_parse().is_none() {
+            return Err(HeaderError::InvalidDataFormat);
+        }
+
+        Ok((header, &bytes[size_of::<Self>()..]))
+    }
+
+    /// Returns the byte order of multi-byte values in the pixel data.
+    pub fn byte_order(&self) -> Endianness {
+        if self.flags & Self::FLAG_BIG_ENDIAN != 0 {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+
+    /// Returns whether the color channels are premultiplied by alpha.
+    pub fn is_premultiplied(&self) -> bool {
+        self.flags & Self::FLAG_PREMULTIPLIED_ALPHA != 0
+    }
+
+    /// Returns whether the first row of the pixel data is the bottom row.
+    pub fn is_origin_bottom_left(&self) -> bool {
+        self.flags & Self::FLAG_ORIGIN_BOTTOM_LEFT != 0
+    }
+
+    /// Returns whether the pixel data contains the full mip chain.
+    pub fn is_mipmapped(&self) -> bool {
+        self.flags & Self::FLAG_MIPMAPPED != 0
+    }
+
+    /// Returns the number of layers in the pixel data, which is at least one.
+    pub fn layer_count(&self) -> u32 {
+        self.layers.max(1)
+    }
+
+    /// Returns whether the file ends with a metadata trailer.
+    pub fn has_metadata(&self) -> bool {
+        self.flags & Self::FLAG_METADATA != 0
+    }
+
+    /// Returns the alignment in bytes of each row of the pixel data, which is
+    /// the data format's unless overridden by the flags.
+    ///
+    /// Returns `None` if the data format is invalid or on overflow.
+    pub fn row_alignment(&self) -> Option<u64> {
+        match (self.flags & Self::ROW_ALIGNMENT_MASK) >> Self::ROW_ALIGNMENT_SHIFT {
+            0 => Some(self.data_format.try_parse()?.row_alignment() as u64),
+            bits => 1u64.checked_shl(bits - 1),
+        }
+    }
+
+    /// Returns the number of pixels in the image, or `None` on overflow.
+    pub fn pixel_count(&self) -> Option<u64> {
+        (self.dimensions[0] as u64).checked_mul(self.dimensions[1] as u64)
+    }
+
+    /// Returns the size in bytes of the pixel data of one layer, including row
+    /// padding.
+    ///
+    /// Returns `None` if the data format is invalid or on overflow.
+    pub fn data_size(&self) -> Option<u64> {
+        let width = self.dimensions[0] as u64;
+        let height = self.dimensions[1] as u64;
+
+        let data_format = self.data_format.try_parse()?;
+
+        match data_format.bits_per_pixel() {
+            Some(bits_per_pixel) => {
+                let row_alignment = self.row_alignment()?;
+                let row_size = (width * bits_per_pixel as u64).div_ceil(8);
+                let row_size = row_size
+                    .div_ceil(row_alignment)
+                    .checked_mul(row_alignment)?;
+
+                row_size.checked_mul(height)
+            }
+            None => width
+                .div_ceil(4)
+                .checked_mul(height.div_ceil(4))?
+                .checked_mul(8),
+        }
+    }
+
+    /// Returns the size in bytes of the header followed by the pixel data of
+    /// every layer, which is the size of a file without mip levels or metadata.
+    ///
+    /// Returns `None` if the data format is invalid or on overflow.
+    pub fn total_size(&self) -> Option<u64> {
+        self.data_size()?
+            .checked_mul(self.layer_count() as u64)?
+            .checked_add(size_of::<Self>() as u64)
+    }
+}
+
+/// Prints the color space, data format and dimensions in a readable form, e.g.
+/// `Header { color_space: SRGB, data_format: RGBA8, dimensions: 256x256 }`.
+impl Display for Header {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "Header {{ color_space: {}, data_format: {}, dimensions: {}x{} }}",
+            self.color_space, self.data_format, self.dimensions[0], self.dimensions[1]
+        )
+    }
+}
+
+/// Parsing error for a header.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HeaderError {
+    /// There are fewer bytes than the size of a header.
+    TooShort,
+    /// The color space is not a known variant.
+    InvalidColorSpace,
+    /// The data format is not a known variant.
+    InvalidDataFormat,
+}
+
+/// Builder for a header, starting from the default header.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeaderBuilder {
+    header: Header,
+}
+
+impl HeaderBuilder {
+    /// Creates a new header builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the color space of the header.
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.header.color_space = color_space.into();
+        self
+    }
+
+    /// Sets the data format of the header.
+    pub fn data_format(mut self, data_format: DataFormat) -> Self {
+        self.header.data_format = data_format.into();
+        self
+    }
+
+    /// Sets the image width and height in pixels.
+    pub fn dimensions(mut self, width: u32, height: u32) -> Self {
+        self.header.dimensions = [width, height];
+        self
+    }
+
+    /// Sets the checksum of the pixel data.
+    pub fn checksum(mut self, checksum: u32) -> Self {
+        self.header.checksum = checksum;
+        self
+    }
+
+    /// Sets the flags of the header.
+    pub fn flags(mut self, flags: u32) -> Self {
+        self.header.flags = flags;
+        self
+    }
+
+    /// Sets the number of layers in the pixel data.
+    pub fn layers(mut self, layers: u32) -> Self {
+        self.header.layers = layers;
+        self
+    }
+
+    /// Sets the version of the tool that created the file.
+    pub fn tool_version(mut self, major: u8, minor: u8, patch: u8) -> Self {
+        self.header.tool_version = (major as u32) << 16 | (minor as u32) << 8 | patch as u32;
+        self
+    }
+
+    /// Sets the creation time of the file in Unix seconds.
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.header.timestamp = timestamp;
+        self
+    }
+
+    /// Returns the header.
+    pub fn build(self) -> Header {
+        self.header
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl Header {
+    /// Computes the CRC32 checksum of some pixel data.
+    pub fn compute_checksum(pixel_data: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(pixel_data);
+        hasher.finalize()
+    }
+
+    /// Returns whether the pixel data matches the checksum in the header.
+    pub fn verify_checksum(&self, pixel_data: &[u8]) -> bool {
+        Self::compute_checksum(pixel_data) == self.checksum
+    }
+}
+
+/// An image made of a header followed by its pixel data.
+#[derive(Clone, Copy, Debug)]
+pub struct RawImage<'a> {
+    header: Header,
+    color_space: ColorSpace,
+    data_format: DataFormat,
+    pixels: &'a [u8],
+    metadata: Option<Metadata<'a>>,
+}
+
+/// Parsing error for a raw image.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RawImageError {
+    /// The header is truncated or has an invalid color space or data format.
+    InvalidHeader,
+    /// There are fewer bytes of pixel data than described by the header.
+    TooShort,
+    /// The header has the metadata flag set but the trailer is invalid.
+    InvalidMetadata,
+}
+
+impl<'a> RawImage<'a> {
+    /// Parses a little-endian header followed by its pixel data.
+    ///
+    /// Any bytes following the pixel data are ignored, except for the metadata
+    /// trailer at the end of the file if the header has the metadata flag set.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, RawImageError> {
+        let (header, data) =
+            Header::parse_slice(bytes).map_err(|_| RawImageError::InvalidHeader)?;
+
+        let pixels = header
+            .data_size()
+            .and_then(|data_size| data_size.checked_mul(header.layer_count() as u64))
+            .and_then(|data_size| usize::try_from(data_size).ok())
+            .and_then(|data_size| data.get(..data_size))
+            .ok_or(RawImageError::TooShort)?;
+
+        let metadata = if header.has_metadata() {
+            Some(Metadata::parse(data).map_err(|_| RawImageError::InvalidMetadata)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            header,
+            color_space: header.color_space.parse_or(ColorSpace::NonColor),
+            data_format: header.data_format.parse_or(DataFormat::R32F),
+            pixels,
+            metadata,
+        })
+    }
+
+    /// Returns the header of the image.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the pixel data of the image, including every layer.
+    pub fn pixels(&self) -> &'a [u8] {
+        self.pixels
+    }
+
+    /// Returns the pixel data of one layer of the image, or `None` if there is
+    /// no such layer. Layers are stored one after the other.
+    pub fn layer(&self, index: u32) -> Option<&'a [u8]> {
+        if index >= self.header.layer_count() {
+            return None;
+        }
+
+        let layer_size = self.pixels.len() / self.header.layer_count() as usize;
+        let start = layer_size * index as usize;
+
+        self.pixels.get(start..start + layer_size)
+    }
+
+    /// Returns the image width in pixels.
+    pub fn width(&self) -> u32 {
+        self.header.dimensions[0]
+    }
+
+    /// Returns the image height in pixels.
+    pub fn height(&self) -> u32 {
+        self.header.dimensions[1]
+    }
+
+    /// Returns the color space of the pixel data.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Returns the data format of the pixel data.
+    pub fn data_format(&self) -> DataFormat {
+        self.data_format
+    }
+
+    /// Returns the metadata of the image, if it has a metadata trailer.
+    pub fn metadata(&self) -> Option<Metadata<'a>> {
+        self.metadata
+    }
+}
+
+/// Key-value metadata pairs stored in a trailer at the end of a file.
+///
+/// The trailer consists of each key and value as a little-endian `u32` length
+/// followed by that many bytes of UTF-8, then the total length of the pairs as
+/// a little-endian `u32`, and finally the four magic bytes `I2RM`.
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata<'a> {
+    pairs: &'a [u8],
+}
+
+/// Parsing error for a metadata trailer.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MetadataError {
+    /// The bytes do not end with the magic bytes of a metadata trailer.
+    MissingTrailer,
+    /// The trailer is longer than the bytes or a pair is truncated.
+    Truncated,
+    /// A key or value is not valid UTF-8.
+    InvalidUtf8,
+}
+
+impl<'a> Metadata<'a> {
+    /// The magic bytes marking the end of a metadata trailer.
+    pub const MAGIC: [u8; 4] = *b"I2RM";
+
+    /// Parses the metadata trailer at the end of a byte slice.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, MetadataError> {
+        let (bytes, magic) = bytes
+            .split_last_chunk::<4>()
+            .ok_or(MetadataError::MissingTrailer)?;
+
+        if *magic != Self::MAGIC {
+            return Err(MetadataError::MissingTrailer);
+        }
+
+        let (bytes, size) = bytes
+            .split_last_chunk::<4>()
+            .ok_or(MetadataError::Truncated)?;
+
+        let pairs = usize::try_from(u32::from_le_bytes(*size))
+            .ok()
+            .and_then(|size| bytes.len().checked_sub(size))
+            .map(|start| &bytes[start..])
+            .ok_or(MetadataError::Truncated)?;
+
+        let mut remaining = pairs;
+
+        while !remaining.is_empty() {
+            read_metadata_string(&mut remaining)?;
+            read_metadata_string(&mut remaining)?;
+        }
+
+        Ok(Self { pairs })
+    }
+
+    /// Returns an iterator over the key-value pairs in order.
+    pub fn iter(&self) -> MetadataIter<'a> {
+        MetadataIter { bytes: self.pairs }
+    }
+
+    /// Returns the value of the first pair with the given key, if any.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, value)| value)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Metadata<'_> {
+    /// Writes a metadata trailer containing the given key-value pairs.
+    pub fn write<W: std::io::Write>(pairs: &[(&str, &str)], mut writer: W) -> std::io::Result<()> {
+        use std::io::{Error, ErrorKind};
+
+        let too_large = || Error::new(ErrorKind::InvalidInput, "metadata is too large");
+
+        let mut size = 0u32;
+
+        for &(key, value) in pairs {
+            for string in [key, value] {
+                let length = u32::try_from(string.len()).map_err(|_| too_large())?;
+
+                writer.write_all(&length.to_le_bytes())?;
+                writer.write_all(string.as_bytes())?;
+
+                size = size
+                    .checked_add(4)
+                    .and_then(|size| size.checked_add(length))
+                    .ok_or_else(too_large)?;
+            }
+        }
+
+        writer.write_all(&size.to_le_bytes())?;
+        writer.write_all(&Self::MAGIC)
+    }
+}
+
+/// Iterator over the key-value pairs of a metadata trailer.
+#[derive(Clone, Debug)]
+pub struct MetadataIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for MetadataIter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The pairs have already been validated when parsing the trailer.
+        let key = read_metadata_string(&mut self.bytes).ok()?;
+        let value = read_metadata_string(&mut self.bytes).ok()?;
+
+        Some((key, value))
+    }
+}
+
+fn read_metadata_string<'a>(bytes: &mut &'a [u8]) -> Result<&'a str, MetadataError> {
+    let (length, rest) = bytes
+        .split_first_chunk::<4>()
+        .ok_or(MetadataError::Truncated)?;
+
+    let (string, rest) = usize::try_from(u32::from_le_bytes(*length))
+        .ok()
+        .and_then(|length| rest.split_at_checked(length))
+        .ok_or(MetadataError::Truncated)?;
+
+    *bytes = rest;
+
+    core::str::from_utf8(string).map_err(|_| MetadataError::InvalidUtf8)
 }
 
 /// Color space information stored in a header.
@@ -27,20 +583,59 @@ pub struct Header {
 /// The header might not be valid, so this is an intermediate struct which is
 /// used to catch invalid bit patterns not representable by any enum variant.
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "zerocopy", derive(AsBytes, FromBytes))]
 pub struct ColorSpaceInfo(u32);
 
 impl ColorSpaceInfo {
+    /// Creates the color space information from a little-endian value, as read
+    /// directly from a little-endian file.
+    pub fn from_le(value: u32) -> Self {
+        Self(u32::from_le(value))
+    }
+
+    /// Creates the color space information from a big-endian value, as read
+    /// directly from a big-endian file.
+    pub fn from_be(value: u32) -> Self {
+        Self(u32::from_be(value))
+    }
+
     /// Returns the inner color space if it is valid.
     pub fn try_parse(self) -> Option<ColorSpace> {
         ColorSpace::try_from_u32(self.0)
     }
+
+    /// Returns the inner color space if it is valid, treating the inner value as
+    /// little-endian.
+    pub fn try_parse_le(self) -> Option<ColorSpace> {
+        Self::from_le(self.0).try_parse()
+    }
+
+    /// Returns the inner color space if it is valid, treating the inner value as
+    /// big-endian.
+    pub fn try_parse_be(self) -> Option<ColorSpace> {
+        Self::from_be(self.0).try_parse()
+    }
+
+    /// Returns the inner color space, or the given default if it is invalid.
+    pub fn parse_or(self, default: ColorSpace) -> ColorSpace {
+        self.try_parse().unwrap_or(default)
+    }
+}
+
+/// Prints the color space name if it is valid, and the raw value otherwise.
+impl Display for ColorSpaceInfo {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.try_parse() {
+            Some(color_space) => write!(f, "{}", color_space),
+            None => write!(f, "{}", self.0),
+        }
+    }
 }
 
 impl From<ColorSpace> for ColorSpaceInfo {
     fn from(color_space: ColorSpace) -> Self {
-        Self(color_space as u32)
+        Self(color_space.as_u32())
     }
 }
 
@@ -49,20 +644,59 @@ impl From<ColorSpace> for ColorSpaceInfo {
 /// The header might not be valid, so this is an intermediate struct which is
 /// used to catch invalid bit patterns not representable by any enum variant.
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "zerocopy", derive(AsBytes, FromBytes))]
 pub struct DataFormatInfo(u32);
 
 impl DataFormatInfo {
+    /// Creates the data format information from a little-endian value, as read
+    /// directly from a little-endian file.
+    pub fn from_le(value: u32) -> Self {
+        Self(u32::from_le(value))
+    }
+
+    /// Creates the data format information from a big-endian value, as read
+    /// directly from a big-endian file.
+    pub fn from_be(value: u32) -> Self {
+        Self(u32::from_be(value))
+    }
+
     /// Returns the inner data format if it is valid.
     pub fn try_parse(self) -> Option<DataFormat> {
         DataFormat::try_from_u32(self.0)
     }
+
+    /// Returns the inner data format if it is valid, treating the inner value as
+    /// little-endian.
+    pub fn try_parse_le(self) -> Option<DataFormat> {
+        Self::from_le(self.0).try_parse()
+    }
+
+    /// Returns the inner data format if it is valid, treating the inner value as
+    /// big-endian.
+    pub fn try_parse_be(self) -> Option<DataFormat> {
+        Self::from_be(self.0).try_parse()
+    }
+
+    /// Returns the inner data format, or the given default if it is invalid.
+    pub fn parse_or(self, default: DataFormat) -> DataFormat {
+        self.try_parse().unwrap_or(default)
+    }
+}
+
+/// Prints the data format name if it is valid, and the raw value otherwise.
+impl Display for DataFormatInfo {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.try_parse() {
+            Some(data_format) => write!(f, "{}", data_format),
+            None => write!(f, "{}", self.0),
+        }
+    }
 }
 
 impl From<DataFormat> for DataFormatInfo {
     fn from(data_format: DataFormat) -> Self {
-        Self(data_format as u32)
+        Self(data_format.as_u32())
     }
 }
 
@@ -70,14 +704,14 @@ impl From<DataFormat> for DataFormatInfo {
 pub struct UnknownVariant {}
 
 macro_rules! gen_enum {
-    ($name:ident, $doc:expr => [$([$variant:ident = $value:expr, $variant_doc:expr],)+]) => {
+    ($name:ident, $doc:expr => [$([$(#[$attr:meta])* $variant:ident = $value:expr, $variant_doc:expr],)+]) => {
         #[repr(u32)]
-        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-        #[doc = $doc] pub enum $name { $(#[doc = $variant_doc] $variant = $value,)+ }
+        #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        #[doc = $doc] pub enum $name { $($(#[$attr])* #[doc = $variant_doc] $variant = $value,)+ }
 
         impl Display for $name {
             fn fmt(&self, f: &mut Formatter) -> FmtResult {
-                match self { $(Self::$variant => write!(f, stringify!($variant)),)+ }
+                match self { $($(#[$attr])* Self::$variant => write!(f, stringify!($variant)),)+ }
             }
         }
 
@@ -86,16 +720,36 @@ macro_rules! gen_enum {
 
             fn from_str(s: &str) -> Result<Self, Self::Err> {
                 match s {
-                    $(stringify!($variant) => Ok(Self::$variant),)+
+                    $($(#[$attr])* stringify!($variant) => Ok(Self::$variant),)+
                     _ => Err(UnknownVariant {})
                 }
             }
         }
 
         impl $name {
+            /// Returns the names of all variants, as accepted by `FromStr`.
+            pub fn names() -> &'static [&'static str] {
+                &[$($(#[$attr])* stringify!($variant),)+]
+            }
+
+            /// Returns all variants, in declaration order.
+            pub fn all() -> &'static [Self] {
+                &[$($(#[$attr])* Self::$variant,)+]
+            }
+
+            /// Returns a one-line description of the variant.
+            pub fn description(self) -> &'static str {
+                match self { $($(#[$attr])* Self::$variant => $variant_doc,)+ }
+            }
+
+            /// Returns the discriminant value of the variant, as stored in headers.
+            pub const fn as_u32(self) -> u32 {
+                self as u32
+            }
+
             pub(crate) fn try_from_u32(value: u32) -> Option<Self> {
                 match value {
-                    $($value => Some(Self::$variant),)+
+                    $($(#[$attr])* $value => Some(Self::$variant),)+
                     _ => None,
                 }
             }
@@ -105,12 +759,18 @@ macro_rules! gen_enum {
 
 // NOTE: the variant discriminant values are specified explicitly here so that
 // they aren't accidentally changed during reordering, which would break code.
+// Variants disabled by a feature keep their value, which is then left unused.
 
 gen_enum!(ColorSpace, "Available color spaces for the pixel data." => [
     [NonColor = 0, "The pixel data does not contain color information."],
     [CIEXYZ = 1, "The CIE XYZ 1931 color space using the D65 illuminant."],
     [SRGB = 2, "The sRGB color space as defined by IEC 61966-2-1:1999."],
     [LinearSRGB = 3, "The sRGB color space but without gamma correction, i.e. linear."],
+    [HSV = 4, "Hue, saturation and value over linear sRGB (source only)."],
+    [AdobeRGB = 5, "The Adobe RGB (1998) color space using the D65 illuminant."],
+    [YCbCr709 = 6, "Full-range YCbCr with BT.709 coefficients over sRGB."],
+    [CIELAB = 7, "The CIE L*a*b* color space using the D65 illuminant."],
+    [CIExyY = 8, "The CIE xyY 1931 color space using the D65 illuminant."],
 ]);
 
 gen_enum!(DataFormat, "Available data formats for the pixel data." => [
@@ -118,13 +778,503 @@ gen_enum!(DataFormat, "Available data formats for the pixel data." => [
     [RG32F = 1, "32-bit floating-point, 4-byte row alignment."],
     [RGBA32F = 2, "32-bit floating-point, 4-byte row alignment."],
     [R8 = 3, "8-bit fixed-point, 4-byte row alignment."],
-    [PackedR8 = 4, "8-bit fixed-point, 1-byte row alignment."],
+    [#[cfg(feature = "packed")] PackedR8 = 4, "8-bit fixed-point, 1-byte row alignment."],
     [R16F = 5, "16-bit floating-point, 4-byte row alignment."],
     [RG16F = 6, "16-bit floating-point, 4-byte row alignment."],
     [RGBA16F = 7, "16-bit floating-point, 4-byte row alignment."],
-    [PackedR16F = 8, "16-bit floating-point, 2-byte row alignment."],
-    [RGBE8 = 9, "8-bit RGBE, alpha is exponent, 4-byte row alignment."],
+    [#[cfg(feature = "packed")] PackedR16F = 8, "16-bit floating-point, 2-byte row alignment."],
+    [#[cfg(feature = "compressed")] RGBE8 = 9, "8-bit RGBE, alpha is exponent, 4-byte row alignment."],
     [RGBA8 = 10, "8-bit fixed-point, 4-byte row alignment."],
-    [BC1 = 11, "BC1 block compression format with no alpha."],
-    [RG8 = 12, "8-bit fixed point, 4-byte row alignment"],
+    [#[cfg(feature = "compressed")] BC1 = 11, "BC1 block compression format with no alpha."],
+    [RG8 = 12, "8-bit fixed-point, 4-byte row alignment."],
+    [RGBA8Snorm = 13, "8-bit signed fixed-point, 4-byte row alignment."],
+    [RG8Snorm = 14, "8-bit signed fixed-point, 4-byte row alignment."],
+    [R32U = 15, "32-bit unsigned integer (not normalized), 4-byte row alignment."],
+    [RGBA32U = 16, "32-bit unsigned integer (not normalized), 4-byte row alignment."],
+    [SRGBA8 = 17, "8-bit fixed-point with sRGB-encoded color, 4-byte row alignment."],
+    [#[cfg(feature = "compressed")] RGB9E5 = 18, "9-bit mantissas with a shared 5-bit exponent, 4-byte row alignment."],
+    [#[cfg(feature = "packed")] PackedR1 = 19, "1-bit fixed-point, 8 pixels per byte MSB-first, 1-byte row alignment."],
+    [#[cfg(feature = "packed")] PackedR4 = 20, "4-bit fixed-point, 2 pixels per byte MSB-first, 1-byte row alignment."],
+    [#[cfg(feature = "compressed")] R11G11B10F = 21, "11-bit and 10-bit unsigned floating-point, 4-byte row alignment."],
 ]);
+
+impl ColorSpace {
+    /// Returns whether the pixel values are linear in light intensity, so that
+    /// they can be filtered directly without being linearized first.
+    ///
+    /// Non-color data is never linearized, so it is also considered linear.
+    pub const fn is_linear(self) -> bool {
+        matches!(self, Self::NonColor | Self::CIEXYZ | Self::LinearSRGB)
+    }
+}
+
+impl DataFormat {
+    /// Returns whether rows are never padded, i.e. tightly packed.
+    ///
+    /// Rows of formats with less than a byte per pixel still start on a byte
+    /// boundary, so the last byte of each row may contain unused bits.
+    pub const fn is_packed(self) -> bool {
+        match self {
+            #[cfg(feature = "packed")]
+            Self::PackedR8 | Self::PackedR16F | Self::PackedR1 | Self::PackedR4 => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the alignment in bytes of the start of each row.
+    ///
+    /// For block-compressed formats, this applies to each row of blocks.
+    pub const fn row_alignment(self) -> usize {
+        match self {
+            #[cfg(feature = "packed")]
+            Self::PackedR8 | Self::PackedR1 | Self::PackedR4 => 1,
+            #[cfg(feature = "packed")]
+            Self::PackedR16F => 2,
+            _ => 4,
+        }
+    }
+
+    /// Returns the number of channels stored for each pixel.
+    pub const fn channel_count(self) -> usize {
+        match self {
+            Self::R32F | Self::R32U | Self::R16F | Self::R8 => 1,
+            #[cfg(feature = "packed")]
+            Self::PackedR16F | Self::PackedR8 | Self::PackedR1 | Self::PackedR4 => 1,
+            Self::RG32F | Self::RG16F | Self::RG8 | Self::RG8Snorm => 2,
+            #[cfg(feature = "compressed")]
+            Self::RGBE8 | Self::RGB9E5 | Self::R11G11B10F | Self::BC1 => 3,
+            Self::RGBA32F
+            | Self::RGBA32U
+            | Self::RGBA16F
+            | Self::RGBA8
+            | Self::RGBA8Snorm
+            | Self::SRGBA8 => 4,
+        }
+    }
+
+    /// Returns the number of bytes per pixel.
+    ///
+    /// Returns `None` for block-compressed formats, which are laid out in 4x4
+    /// blocks of pixels instead, and for formats with less than a byte per
+    /// pixel, see `bits_per_pixel`.
+    pub const fn bytes_per_pixel(self) -> Option<usize> {
+        match self {
+            #[cfg(feature = "packed")]
+            Self::PackedR1 | Self::PackedR4 => None,
+            Self::R32F => Some(4),
+            Self::RG32F => Some(8),
+            Self::RGBA32F => Some(16),
+            Self::R8 => Some(1),
+            #[cfg(feature = "packed")]
+            Self::PackedR8 => Some(1),
+            Self::R16F => Some(2),
+            Self::RG16F => Some(4),
+            Self::RGBA16F => Some(8),
+            #[cfg(feature = "packed")]
+            Self::PackedR16F => Some(2),
+            #[cfg(feature = "compressed")]
+            Self::RGBE8 => Some(4),
+            Self::RGBA8 => Some(4),
+            #[cfg(feature = "compressed")]
+            Self::BC1 => None,
+            Self::RG8 => Some(2),
+            Self::RGBA8Snorm => Some(4),
+            Self::RG8Snorm => Some(2),
+            Self::R32U => Some(4),
+            Self::RGBA32U => Some(16),
+            Self::SRGBA8 => Some(4),
+            #[cfg(feature = "compressed")]
+            Self::RGB9E5 => Some(4),
+            #[cfg(feature = "compressed")]
+            Self::R11G11B10F => Some(4),
+        }
+    }
+
+    /// Returns the number of bits per pixel.
+    ///
+    /// Returns `None` for block-compressed formats, which are laid out in 4x4
+    /// blocks of pixels instead.
+    pub const fn bits_per_pixel(self) -> Option<usize> {
+        match self {
+            #[cfg(feature = "packed")]
+            Self::PackedR1 => Some(1),
+            #[cfg(feature = "packed")]
+            Self::PackedR4 => Some(4),
+            _ => match self.bytes_per_pixel() {
+                Some(bytes_per_pixel) => Some(bytes_per_pixel * 8),
+                None => None,
+            },
+        }
+    }
+}
+
+/// Validation error for a color space and data format combination.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CombinationError {
+    /// The color space can only be used as a source color space.
+    SourceOnlyColorSpace,
+    /// The data format requires the `SRGB` or `LinearSRGB` color space.
+    RequiresSRGB,
+    /// The source is non-color data but the output color space is not `NonColor`.
+    NonColorSource,
+    /// The output is non-color data but the source color space is not `NonColor`.
+    NonColorOutput,
+    /// The data format has a single channel but the first channel of the color
+    /// space is not a measure of brightness, e.g. X in `CIEXYZ`.
+    RequiresLuminance,
+}
+
+/// Checks whether pixel data in a color space can be stored in a data format.
+///
+/// This only covers the color space of the stored pixel data; for instance,
+/// `HSV` is rejected because it can only be used as a source color space. See
+/// [`is_valid_conversion`] for the rules between the source and the output.
+pub fn is_valid_combination(
+    color_space: ColorSpace,
+    data_format: DataFormat,
+) -> Result<(), CombinationError> {
+    if color_space == ColorSpace::HSV {
+        return Err(CombinationError::SourceOnlyColorSpace);
+    }
+
+    if data_format.channel_count() == 1
+        && matches!(color_space, ColorSpace::CIEXYZ | ColorSpace::CIExyY)
+    {
+        return Err(CombinationError::RequiresLuminance);
+    }
+
+    if data_format == DataFormat::SRGBA8
+        && !matches!(color_space, ColorSpace::SRGB | ColorSpace::LinearSRGB)
+    {
+        return Err(CombinationError::RequiresSRGB);
+    }
+
+    Ok(())
+}
+
+/// Checks whether pixel data in a source color space can be converted into an
+/// output color space.
+///
+/// Non-color data cannot be converted to or from any other color space.
+pub fn is_valid_conversion(
+    source_color_space: ColorSpace,
+    color_space: ColorSpace,
+) -> Result<(), CombinationError> {
+    if source_color_space != color_space {
+        if source_color_space == ColorSpace::NonColor {
+            return Err(CombinationError::NonColorSource);
+        }
+
+        if color_space == ColorSpace::NonColor {
+            return Err(CombinationError::NonColorOutput);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes(header: &Header, endianness: Endianness) -> [u8; 40] {
+        let fields = [
+            header.color_space.0,
+            header.data_format.0,
+            header.dimensions[0],
+            header.dimensions[1],
+            header.checksum,
+            header.flags,
+            header.tool_version,
+            header.layers,
+        ];
+
+        let mut bytes = [0; 40];
+
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(&fields) {
+            chunk.copy_from_slice(&match endianness {
+                Endianness::Little => field.to_le_bytes(),
+                Endianness::Big => field.to_be_bytes(),
+            });
+        }
+
+        bytes[32..].copy_from_slice(&match endianness {
+            Endianness::Little => header.timestamp.to_le_bytes(),
+            Endianness::Big => header.timestamp.to_be_bytes(),
+        });
+
+        bytes
+    }
+
+    fn sample_header() -> Header {
+        HeaderBuilder::new()
+            .color_space(ColorSpace::SRGB)
+            .data_format(DataFormat::RGBA8)
+            .dimensions(640, 480)
+            .checksum(0xdead_beef)
+            .flags(Header::FLAG_BIG_ENDIAN)
+            .tool_version(0, 4, 0)
+            .timestamp(0x0123_4567_89ab_cdef)
+            .build()
+    }
+
+    #[test]
+    #[cfg(feature = "checksum")]
+    fn corrupted_pixel_data_fails_checksum() {
+        let mut pixel_data = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+
+        let header = HeaderBuilder::new()
+            .checksum(Header::compute_checksum(&pixel_data))
+            .build();
+
+        assert!(header.verify_checksum(&pixel_data));
+
+        pixel_data[3] ^= 0x01;
+        assert!(!header.verify_checksum(&pixel_data));
+    }
+
+    #[test]
+    fn builder_matches_struct_literal() {
+        let header = Header {
+            color_space: ColorSpace::LinearSRGB.into(),
+            data_format: DataFormat::R16F.into(),
+            dimensions: [3, 5],
+            checksum: 0xdead_beef,
+            flags: Header::FLAG_BIG_ENDIAN,
+            ..Header::default()
+        };
+
+        let built = HeaderBuilder::new()
+            .color_space(ColorSpace::LinearSRGB)
+            .data_format(DataFormat::R16F)
+            .dimensions(3, 5)
+            .checksum(0xdead_beef)
+            .flags(Header::FLAG_BIG_ENDIAN)
+            .build();
+
+        assert_eq!(built, header);
+    }
+
+    #[test]
+    fn huge_dimensions_overflow_data_size() {
+        let header = HeaderBuilder::new()
+            .data_format(DataFormat::RGBA32F)
+            .dimensions(u32::MAX, u32::MAX)
+            .build();
+
+        assert_eq!(
+            header.pixel_count(),
+            Some(u32::MAX as u64 * u32::MAX as u64)
+        );
+        assert_eq!(header.data_size(), None);
+        assert_eq!(header.total_size(), None);
+    }
+
+    #[test]
+    fn big_endian_header_reads_like_little_endian() {
+        let header = sample_header();
+
+        let le_bytes = header_bytes(&header, Endianness::Little);
+        let be_bytes = header_bytes(&header, Endianness::Big);
+        assert_ne!(le_bytes, be_bytes);
+
+        assert_eq!(Header::from_bytes(&le_bytes), Some(header));
+        assert_eq!(
+            Header::from_bytes_endian(&be_bytes, Endianness::Big),
+            Some(header)
+        );
+        assert_ne!(Header::from_bytes(&be_bytes), Some(header));
+        assert_eq!(Header::from_bytes(&le_bytes[..39]), None);
+    }
+
+    #[test]
+    fn header_layout_matches_file_layout() {
+        assert_eq!(size_of::<Header>(), 40);
+        assert_eq!(offset_of!(Header, checksum), 16);
+        assert_eq!(offset_of!(Header, timestamp), 32);
+    }
+
+    #[test]
+    #[cfg(all(feature = "zerocopy", target_endian = "little"))]
+    fn header_bytes_match_file_layout() {
+        let header = sample_header();
+        assert_eq!(header.as_bytes(), header_bytes(&header, Endianness::Little));
+    }
+
+    #[test]
+    fn invalid_info_parses_to_default() {
+        assert_eq!(
+            ColorSpaceInfo(999).parse_or(ColorSpace::SRGB),
+            ColorSpace::SRGB
+        );
+        assert_eq!(DataFormatInfo(999).parse_or(DataFormat::R8), DataFormat::R8);
+
+        let info = ColorSpaceInfo::from(ColorSpace::CIELAB);
+        assert_eq!(info.parse_or(ColorSpace::SRGB), ColorSpace::CIELAB);
+    }
+
+    #[test]
+    fn raw_image_parses_header_and_pixels() {
+        let header = HeaderBuilder::new()
+            .color_space(ColorSpace::SRGB)
+            .data_format(DataFormat::R8)
+            .dimensions(3, 2)
+            .build();
+
+        let mut bytes = [0xff; 48];
+        bytes[..40].copy_from_slice(&header_bytes(&header, Endianness::Little));
+
+        let image = RawImage::parse(&bytes).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.color_space(), ColorSpace::SRGB);
+        assert_eq!(image.data_format(), DataFormat::R8);
+        assert_eq!(image.pixels().len(), 8);
+
+        assert_eq!(
+            RawImage::parse(&bytes[..47]).err(),
+            Some(RawImageError::TooShort)
+        );
+        assert_eq!(
+            RawImage::parse(&bytes[..39]).err(),
+            Some(RawImageError::InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn parse_slice_returns_bytes_after_header() {
+        let mut bytes = [0xab; 45];
+        bytes[..40].copy_from_slice(&header_bytes(&sample_header(), Endianness::Little));
+
+        let (header, rest) = Header::parse_slice(&bytes).unwrap();
+        assert_eq!(header, sample_header());
+        assert_eq!(rest.len(), bytes.len() - 40);
+
+        bytes[0] = 0xff;
+        let error = Header::parse_slice(&bytes).err();
+        assert_eq!(error, Some(HeaderError::InvalidColorSpace));
+    }
+
+    #[test]
+    fn valid_combinations() {
+        use ColorSpace::*;
+        use CombinationError::*;
+
+        let cases = [
+            (SRGB, DataFormat::RGBA8, Ok(())),
+            (LinearSRGB, DataFormat::SRGBA8, Ok(())),
+            (LinearSRGB, DataFormat::R8, Ok(())),
+            (CIELAB, DataFormat::R32F, Ok(())),
+            (NonColor, DataFormat::R8, Ok(())),
+            (CIExyY, DataFormat::RGBA32F, Ok(())),
+            (HSV, DataFormat::RGBA32F, Err(SourceOnlyColorSpace)),
+            (AdobeRGB, DataFormat::SRGBA8, Err(RequiresSRGB)),
+            (CIEXYZ, DataFormat::R16F, Err(RequiresLuminance)),
+            (CIExyY, DataFormat::R32F, Err(RequiresLuminance)),
+        ];
+
+        for &(color_space, data_format, expected) in &cases {
+            assert_eq!(
+                is_valid_combination(color_space, data_format),
+                expected,
+                "{} as {}",
+                color_space,
+                data_format
+            );
+        }
+    }
+
+    #[test]
+    fn valid_conversions() {
+        use ColorSpace::*;
+        use CombinationError::*;
+
+        let cases = [
+            (SRGB, SRGB, Ok(())),
+            (HSV, LinearSRGB, Ok(())),
+            (CIEXYZ, CIELAB, Ok(())),
+            (NonColor, NonColor, Ok(())),
+            (NonColor, SRGB, Err(NonColorSource)),
+            (SRGB, NonColor, Err(NonColorOutput)),
+        ];
+
+        for &(source, output, expected) in &cases {
+            assert_eq!(
+                is_valid_conversion(source, output),
+                expected,
+                "{} -> {}",
+                source,
+                output
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn metadata_round_trips() {
+        let pairs = [("author", "img2raw"), ("source", "textures/brick.png")];
+
+        let mut bytes = std::vec![0xcd; 16];
+        Metadata::write(&pairs, &mut bytes).unwrap();
+
+        let metadata = Metadata::parse(&bytes).unwrap();
+        assert!(metadata.iter().eq(pairs.iter().copied()));
+        assert_eq!(metadata.get("source"), Some("textures/brick.png"));
+        assert_eq!(metadata.get("missing"), None);
+
+        let error = Metadata::parse(&bytes[..bytes.len() - 1]).err();
+        assert_eq!(error, Some(MetadataError::MissingTrailer));
+    }
+
+    #[test]
+    fn info_wrappers_parse_raw_file_values() {
+        let color_space = ColorSpace::SRGB.as_u32();
+        let data_format = DataFormat::RGBA8.as_u32();
+
+        let info = ColorSpaceInfo(color_space.to_be());
+        assert_eq!(info.try_parse_be(), Some(ColorSpace::SRGB));
+        assert_eq!(
+            ColorSpaceInfo::from_be(color_space.to_be()),
+            ColorSpace::SRGB.into()
+        );
+
+        let info = ColorSpaceInfo(color_space.to_le());
+        assert_eq!(info.try_parse_le(), Some(ColorSpace::SRGB));
+
+        let info = DataFormatInfo(data_format.to_be());
+        assert_eq!(info.try_parse_be(), Some(DataFormat::RGBA8));
+        assert_eq!(
+            DataFormatInfo::from_be(data_format.to_be()),
+            DataFormat::RGBA8.into()
+        );
+
+        assert_eq!(ColorSpaceInfo(color_space.swap_bytes()).try_parse(), None);
+    }
+
+    #[test]
+    fn linear_color_spaces() {
+        for &color_space in ColorSpace::all() {
+            let expected = matches!(
+                color_space,
+                ColorSpace::NonColor | ColorSpace::CIEXYZ | ColorSpace::LinearSRGB
+            );
+
+            assert_eq!(color_space.is_linear(), expected, "{}", color_space);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "compressed"))]
+    fn compressed_formats_are_invalid_without_feature() {
+        assert_eq!(DataFormatInfo(11).try_parse(), None);
+        assert_eq!(DataFormatInfo(11u32.to_le()).try_parse_le(), None);
+        assert!("BC1".parse::<DataFormat>().is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "packed"))]
+    fn packed_formats_are_invalid_without_feature() {
+        assert_eq!(DataFormatInfo(19).try_parse(), None);
+        assert_eq!(DataFormatInfo(19u32.to_le()).try_parse_le(), None);
+        assert!("PackedR1".parse::<DataFormat>().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// The tool is a binary crate, so its source is included directly into this
+// module, giving the benchmarks access to the private store and conversion
+// functions without having to expose them through a library.
+#[allow(
+    dead_code,
+    clippy::items_after_test_module,
+    clippy::upper_case_acronyms
+)]
+mod tool {
+    include!("../src/main.rs");
+
+    use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+    use std::io::sink;
+
+    const WIDTH: u32 = 512;
+    const HEIGHT: u32 = 512;
+
+    fn synthetic_image() -> Image {
+        let mut image = Image::new(WIDTH, HEIGHT).unwrap();
+
+        for (index, pixel) in image.pixels.iter_mut().enumerate() {
+            let x = (index as u32 % WIDTH) as f64 / (WIDTH - 1) as f64;
+            let y = (index as u32 / WIDTH) as f64 / (HEIGHT - 1) as f64;
+
+            *pixel = Pixel::from_array([x, y, (x + y) / 2.0, 1.0 - x * y]);
+        }
+
+        image
+    }
+
+    pub fn store(c: &mut Criterion) {
+        let image = synthetic_image();
+
+        let mut group = c.benchmark_group("store");
+        group.throughput(Throughput::Elements(image.pixels.len() as u64));
+
+        for &data_format in DataFormat::all() {
+            group.bench_with_input(
+                BenchmarkId::from_parameter(data_format),
+                &data_format,
+                |b, &data_format| {
+                    b.iter(|| {
+                        store_pixels(
+                            &image,
+                            data_format,
+                            None,
+                            Endianness::Little,
+                            Rounding::RoundEven,
+                            sink(),
+                        )
+                        .unwrap()
+                    })
+                },
+            );
+        }
+
+        group.finish();
+    }
+
+    pub fn color_conversion(c: &mut Criterion) {
+        let image = synthetic_image();
+
+        let mut group = c.benchmark_group("color_conversion");
+        group.throughput(Throughput::Elements(image.pixels.len() as u64));
+
+        for &color_space in &[ColorSpace::SRGB, ColorSpace::AdobeRGB, ColorSpace::CIELAB] {
+            group.bench_with_input(
+                BenchmarkId::from_parameter(color_space),
+                &color_space,
+                |b, &color_space| {
+                    b.iter(|| {
+                        image
+                            .pixels
+                            .par_iter()
+                            .map(|pixel| {
+                                pixel
+                                    .convert_into_cie_xyz(color_space, None)
+                                    .convert_from_cie_xyz(ColorSpace::LinearSRGB, None)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                },
+            );
+        }
+
+        group.finish();
+    }
+
+    // Compares evaluating the sRGB transfer function with the power function
+    // against the table lookup used for values loaded from 8-bit images.
+    pub fn srgb_gamma(c: &mut Criterion) {
+        let values: Vec<f64> = (0..=255).map(|x| x as f64 / 255.0).collect();
+
+        let mut group = c.benchmark_group("srgb_gamma");
+        group.throughput(Throughput::Elements(values.len() as u64));
+
+        group.bench_function("pow", |b| {
+            b.iter(|| {
+                for &x in &values {
+                    black_box(Pixel::evaluate_gamma_srgb_inverse(black_box(x)));
+                }
+            })
+        });
+
+        group.bench_function("table", |b| {
+            b.iter(|| {
+                for &x in &values {
+                    black_box(Pixel::convert_from_gamma_srgb(black_box(x)));
+                }
+            })
+        });
+
+        group.finish();
+    }
+}
+
+use criterion::{criterion_group, criterion_main};
+
+criterion_group!(
+    benches,
+    tool::store,
+    tool::color_conversion,
+    tool::srgb_gamma
+);
+criterion_main!(benches);
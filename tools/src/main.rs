@@ -1,462 +1,5818 @@
-use byteorder::{WriteBytesExt, LE};
+use byteorder::{ByteOrder, WriteBytesExt, BE, LE};
 use exitfailure::ExitFailure;
 use failure::{bail, Error};
 use half::f16;
-use image::{guess_format, hdr, load_from_memory, ImageFormat};
-use img2raw::{ColorSpace, DataFormat, Header};
+use image::{
+    gif, guess_format, hdr, imageops, load_from_memory, AnimationDecoder, DynamicImage, FilterType,
+    GenericImageView, ImageBuffer, ImageFormat, Rgba, RgbaImage,
+};
+use img2raw::{
+    decode_pixels, is_valid_combination, is_valid_conversion, ColorSpace, CombinationError,
+    DataFormat, Endianness, Header, HeaderBuilder, HeaderError, Metadata, RawImage, RawImageError,
+};
 use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use squish::{Algorithm, Format};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env::var;
 use std::fs::{read, File};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::io::{stdin, stdout, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::ops::{Index, IndexMut};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
+use thiserror::Error as ThisError;
 use zerocopy::AsBytes;
 
 #[derive(Debug, StructOpt)]
 #[structopt(about, author, name = "img2raw")]
 struct Arguments {
-    #[structopt(long, parse(try_from_str = parse_color_space))]
-    source_color_space: ColorSpace,
+    #[structopt(long, required_unless_one = &["info", "verify", "list-formats", "transcode", "passthrough", "retag"], parse(try_from_str = parse_source_color_space))]
+    source_color_space: Option<SourceColorSpace>,
 
-    #[structopt(long, parse(try_from_str = parse_color_space))]
-    output_color_space: ColorSpace,
+    #[structopt(long, required_unless_one = &["info", "verify", "list-formats", "passthrough", "retag"], parse(try_from_str = parse_color_space))]
+    output_color_space: Option<ColorSpace>,
 
-    #[structopt(long = "format", parse(try_from_str = parse_data_format))]
-    output_data_format: DataFormat,
+    #[structopt(long = "format", required_unless_one = &["info", "verify", "list-formats", "retag"], parse(try_from_str = parse_data_format))]
+    output_data_format: Option<DataFormat>,
+
+    #[structopt(parse(from_os_str), required_unless_one = &["info", "verify", "list-formats"])]
+    files: Vec<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), conflicts_with = "files")]
+    info: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["files", "info"])]
+    verify: Option<PathBuf>,
+
+    #[structopt(long, conflicts_with_all = &["files", "info", "verify"])]
+    list_formats: bool,
+
+    #[structopt(long, parse(from_os_str))]
+    output_dir: Option<PathBuf>,
 
-    #[structopt(parse(from_os_str))]
-    source_file: PathBuf,
+    #[structopt(long, conflicts_with_all = &["output-dir", "mips", "dry-run", "strip-height", "preallocate", "alpha-out", "palette"])]
+    layers: bool,
 
-    #[structopt(parse(from_os_str))]
-    output_file: PathBuf,
+    #[structopt(long, conflicts_with = "source-color-space")]
+    transcode: bool,
+
+    #[structopt(long, conflicts_with_all = &["source-color-space", "transcode", "passthrough", "output-dir", "layers"])]
+    retag: bool,
+
+    #[structopt(long, conflicts_with_all = &["source-color-space", "output-color-space"])]
+    passthrough: bool,
 
     #[structopt(long)]
     header: bool,
-}
 
-fn parse_color_space(input: &str) -> Result<ColorSpace, Error> {
-    if let Ok(color_space) = input.parse() {
-        Ok(color_space)
-    } else {
-        bail!("unknown color space {}", input)
-    }
-}
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["header", "output-dir"])]
+    header_file: Option<PathBuf>,
 
-fn parse_data_format(input: &str) -> Result<DataFormat, Error> {
-    if let Ok(data_format) = input.parse() {
-        Ok(data_format)
-    } else {
-        bail!("unknown data format {}", input)
-    }
-}
+    #[structopt(long, requires = "header-file", parse(try_from_str = parse_header_format))]
+    header_format: Option<HeaderFormat>,
 
-fn main() -> Result<(), ExitFailure> {
-    Ok(run()?)
-}
+    #[structopt(long, parse(from_os_str), conflicts_with = "output-dir")]
+    alpha_out: Option<PathBuf>,
 
-fn run() -> Result<(), Error> {
-    let args = Arguments::from_args();
+    #[structopt(long, requires = "palette-out", conflicts_with_all = &["mips", "dither", "alpha-out", "strip-height", "swizzle", "channels"], parse(try_from_str = parse_palette_size))]
+    palette: Option<usize>,
 
-    let bytes = read(args.source_file)?;
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "palette",
+        conflicts_with = "output-dir"
+    )]
+    palette_out: Option<PathBuf>,
 
-    let mut image = match guess_format(&bytes)? {
-        ImageFormat::HDR => load_hdr_image(&bytes)?,
-        ImageFormat::PNG => load_dynamic_image(&bytes)?,
-        ImageFormat::JPEG => load_dynamic_image(&bytes)?,
-        ImageFormat::PNM => load_dynamic_image(&bytes)?,
-        ImageFormat::BMP => load_dynamic_image(&bytes)?,
-        ImageFormat::TIFF => load_dynamic_image(&bytes)?,
-        unsupported => bail!("unsupported file type: {:?}", unsupported),
-    };
+    #[structopt(long = "meta", number_of_values = 1, parse(try_from_str = parse_metadata))]
+    metadata: Vec<(String, String)>,
 
-    let source_color_space = args.source_color_space;
-    let output_color_space = args.output_color_space;
+    #[structopt(long)]
+    dither: bool,
 
-    if source_color_space != output_color_space {
-        if source_color_space == ColorSpace::NonColor {
-            bail!("non-color source requires non-color output");
-        }
+    #[structopt(long, default_value = "round-even", parse(try_from_str = parse_rounding))]
+    rounding: Rounding,
 
-        if output_color_space == ColorSpace::NonColor {
-            bail!("non-color output requires non-color source");
-        }
+    #[structopt(long)]
+    flip_y: bool,
 
-        image.pixels.par_iter_mut().for_each(|pixel| {
-            *pixel = pixel.convert_into_cie_xyz(source_color_space);
-            *pixel = pixel.convert_from_cie_xyz(output_color_space);
-        });
-    }
+    #[structopt(long, parse(try_from_str = parse_dimensions))]
+    resize: Option<(u32, u32)>,
 
-    let mut file = BufWriter::new(File::create(args.output_file)?);
+    #[structopt(long, default_value = "triangle", parse(try_from_str = parse_resize_filter))]
+    resize_filter: ResizeFilter,
 
-    if args.header {
-        let header = Header {
-            color_space: args.output_color_space.into(),
-            data_format: args.output_data_format.into(),
-            dimensions: [image.width, image.height],
-        };
+    #[structopt(long, requires = "resize", parse(try_from_str = parse_supersample))]
+    supersample: Option<u32>,
 
-        file.write_all(header.as_bytes())?;
-    }
+    #[structopt(long)]
+    mips: bool,
 
-    match args.output_data_format {
-        DataFormat::R32F => store_r32f_pixels(&image, file)?,
-        DataFormat::RG32F => store_rg32f_pixels(&image, file)?,
-        DataFormat::RGBA32F => store_rgba32f_pixels(&image, file)?,
-        DataFormat::R8 => store_r8_pixels(&image, file)?,
-        DataFormat::PackedR8 => store_packed_r8_pixels(&image, file)?,
-        DataFormat::R16F => store_r16f_pixels(&image, file)?,
-        DataFormat::RG16F => store_rg16f_pixels(&image, file)?,
-        DataFormat::RGBA16F => store_rgba16f_pixels(&image, file)?,
-        DataFormat::PackedR16F => store_packed_r16f_pixels(&image, file)?,
-        DataFormat::RGBE8 => store_rgbe8_pixels(&image, file)?,
-        DataFormat::RGBA8 => store_rgba8_pixels(&image, file)?,
-        DataFormat::BC1 => store_bc1_pixels(&image, file)?,
-        DataFormat::RG8 => store_rg8_pixels(&image, file)?,
-    }
+    #[structopt(long)]
+    premultiply: bool,
 
-    println!(
-        "{:?} {:?} {} {}",
-        args.output_color_space, args.output_data_format, image.width, image.height
-    );
+    #[structopt(long)]
+    source_premultiplied: bool,
 
-    Ok(())
-}
+    #[structopt(long, default_value = "0")]
+    threads: usize,
 
-// Input
+    #[structopt(long)]
+    clamp: bool,
 
-fn load_dynamic_image(bytes: &[u8]) -> Result<Image, Error> {
-    let data = load_from_memory(bytes)?.to_rgba();
+    #[structopt(long, allow_hyphen_values = true)]
+    clamp_min: Option<f64>,
 
-    let mut image = Image::new(data.width(), data.height());
+    #[structopt(long, allow_hyphen_values = true)]
+    clamp_max: Option<f64>,
 
-    for (input, pixel) in data.pixels().zip(&mut image.pixels) {
-        pixel.r = input.0[0] as f64 / 255.0;
-        pixel.g = input.0[1] as f64 / 255.0;
-        pixel.b = input.0[2] as f64 / 255.0;
-        pixel.a = input.0[3] as f64 / 255.0;
-    }
+    #[structopt(long)]
+    sanitize: bool,
 
-    Ok(image)
-}
+    #[structopt(long)]
+    strict: bool,
 
-fn load_hdr_image(bytes: &[u8]) -> Result<Image, Error> {
-    let loaded = hdr::HDRDecoder::new(bytes)?;
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    exposure: f64,
 
-    let metadata = loaded.metadata();
+    #[structopt(long, parse(try_from_str = parse_tone_mapping))]
+    tonemap: Option<ToneMapping>,
 
-    let data = loaded.read_image_hdr()?;
+    #[structopt(long, conflicts_with = "strip-height")]
+    normalize: bool,
 
-    let mut image = Image::new(metadata.width, metadata.height);
+    #[structopt(long, parse(try_from_str = parse_row_alignment))]
+    row_align: Option<usize>,
 
-    for (input, pixel) in data.iter().zip(&mut image.pixels) {
-        pixel.r = input.0[0] as f64;
-        pixel.g = input.0[1] as f64;
-        pixel.b = input.0[2] as f64;
-    }
+    #[structopt(long)]
+    reconstruct_z: bool,
 
-    Ok(image)
-}
+    #[structopt(long, default_value = "keep", parse(try_from_str = parse_alpha_policy))]
+    alpha: AlphaPolicy,
 
-// Processing
+    #[structopt(long, parse(from_os_str), conflicts_with = "strip-height")]
+    alpha_from: Option<PathBuf>,
 
-#[derive(Debug)]
-pub struct Image {
-    pub width: u32,
-    pub height: u32,
-    pub pixels: Vec<Pixel>,
-}
+    #[structopt(long, conflicts_with_all = &["strip-height", "transcode"])]
+    frame: Option<usize>,
 
-impl Image {
-    pub fn new(width: u32, height: u32) -> Self {
-        Self {
-            width,
-            height,
-            pixels: vec![Pixel::default(); (width * height) as usize],
-        }
-    }
-}
+    #[structopt(long, default_value = "linear", parse(try_from_str = parse_alpha_transfer))]
+    alpha_transfer: AlphaTransfer,
 
-#[derive(Default, Clone, Copy, Debug)]
-pub struct Pixel {
-    pub r: f64,
-    pub g: f64,
-    pub b: f64,
-    pub a: f64,
-}
+    #[structopt(long, parse(try_from_str = parse_swizzle))]
+    swizzle: Option<Swizzle>,
 
-impl Pixel {
-    pub fn convert_into_cie_xyz(self, color_space: ColorSpace) -> Self {
-        match color_space {
-            ColorSpace::NonColor | ColorSpace::CIEXYZ => self,
-            ColorSpace::LinearSRGB => self.convert_into_cie_xyz_from_linear_srgb(),
-            ColorSpace::SRGB => {
-                let mut pixel = self;
+    #[structopt(long, conflicts_with = "swizzle", parse(try_from_str = parse_channels))]
+    channels: Option<Swizzle>,
 
-                pixel.r = Self::convert_from_gamma_srgb(pixel.r);
-                pixel.g = Self::convert_from_gamma_srgb(pixel.g);
-                pixel.b = Self::convert_from_gamma_srgb(pixel.b);
+    #[structopt(long, parse(try_from_str = parse_gamma))]
+    gamma: Option<f64>,
 
-                pixel.convert_into_cie_xyz_from_linear_srgb()
-            }
-        }
-    }
+    #[structopt(long, default_value = "0.2126,0.7152,0.0722", parse(try_from_str = parse_luma_weights))]
+    luma_weights: [f64; 3],
 
-    pub fn convert_from_cie_xyz(self, color_space: ColorSpace) -> Self {
-        match color_space {
-            ColorSpace::NonColor | ColorSpace::CIEXYZ => self,
-            ColorSpace::LinearSRGB => self.convert_from_cie_xyz_into_linear_srgb(),
-            ColorSpace::SRGB => {
-                let mut pixel = self.convert_from_cie_xyz_into_linear_srgb();
+    #[structopt(long, default_value = "le", parse(try_from_str = parse_byte_order))]
+    byte_order: Endianness,
 
-                pixel.r = Self::convert_into_gamma_srgb(pixel.r);
-                pixel.g = Self::convert_into_gamma_srgb(pixel.g);
-                pixel.b = Self::convert_into_gamma_srgb(pixel.b);
+    #[structopt(long)]
+    dry_run: bool,
 
-                pixel
-            }
-        }
+    #[structopt(long)]
+    preallocate: bool,
+
+    #[structopt(long, conflicts_with_all = &["resize", "mips", "flip-y", "dither", "alpha-out", "preallocate"])]
+    strip_height: Option<u32>,
+
+    #[structopt(long, default_value = "268435456")]
+    max_pixels: u64,
+
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+fn parse_color_space(input: &str) -> Result<ColorSpace, Error> {
+    if let Ok(color_space) = input.parse() {
+        Ok(color_space)
+    } else {
+        bail!(
+            "unknown color space {}, expected one of {}",
+            input,
+            list_names(ColorSpace::names())
+        )
     }
+}
 
-    fn convert_into_cie_xyz_from_linear_srgb(self) -> Self {
-        let mut pixel = self;
+fn parse_source_color_space(input: &str) -> Result<SourceColorSpace, Error> {
+    if input == "auto" {
+        Ok(SourceColorSpace::Auto)
+    } else {
+        Ok(SourceColorSpace::Known(parse_color_space(input)?))
+    }
+}
 
-        pixel.r = 0.4124 * self.r + 0.3576 * self.g + 0.1805 * self.b;
-        pixel.g = 0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b;
-        pixel.b = 0.0193 * self.r + 0.1192 * self.g + 0.9505 * self.b;
+fn list_names(names: &[&str]) -> String {
+    let mut names = names.to_vec();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.join(", ")
+}
 
-        pixel
+fn parse_data_format(input: &str) -> Result<DataFormat, Error> {
+    if let Ok(data_format) = input.parse() {
+        Ok(data_format)
+    } else {
+        bail!(
+            "unknown data format {}, expected one of {}",
+            input,
+            list_names(DataFormat::names())
+        )
     }
+}
 
-    fn convert_from_cie_xyz_into_linear_srgb(self) -> Self {
-        let mut pixel = self;
+fn parse_dimensions(input: &str) -> Result<(u32, u32), Error> {
+    let mut parts = input.splitn(2, 'x');
 
-        pixel.r = 3.2406 * self.r - 1.5372 * self.g - 0.4986 * self.b;
-        pixel.g = -0.9689 * self.r + 1.8758 * self.g + 0.0415 * self.b;
-        pixel.b = 0.0557 * self.r - 0.2040 * self.g + 1.0570 * self.b;
+    match (parts.next(), parts.next()) {
+        (Some(width), Some(height)) => Ok((width.parse()?, height.parse()?)),
+        _ => bail!("expected dimensions as WIDTHxHEIGHT, got {}", input),
+    }
+}
 
-        pixel
+fn parse_metadata(input: &str) -> Result<(String, String), Error> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => bail!("expected metadata as KEY=VALUE, got {}", input),
     }
+}
 
-    fn convert_into_gamma_srgb(x: f64) -> f64 {
-        if x <= 0.003_130_8 {
-            12.92 * x
-        } else {
-            1.055 * x.powf(1.0 / 2.4) - 0.055
-        }
+fn parse_resize_filter(input: &str) -> Result<ResizeFilter, Error> {
+    match input {
+        "box" => Ok(ResizeFilter::Box),
+        "nearest" => Ok(ResizeFilter::Filter(FilterType::Nearest)),
+        "triangle" => Ok(ResizeFilter::Filter(FilterType::Triangle)),
+        "lanczos" => Ok(ResizeFilter::Filter(FilterType::Lanczos3)),
+        _ => bail!("unknown resize filter {}", input),
     }
+}
 
-    fn convert_from_gamma_srgb(x: f64) -> f64 {
-        if x <= 0.040_45 {
-            x / 12.92
-        } else {
-            ((x + 0.055) / 1.055).powf(2.4)
-        }
+fn parse_supersample(input: &str) -> Result<u32, Error> {
+    match input.parse()? {
+        0 => bail!("supersampling requires at least one sample"),
+        samples => Ok(samples),
     }
 }
 
-// Output
+fn parse_row_alignment(input: &str) -> Result<usize, Error> {
+    match input.parse()? {
+        alignment @ 1..=65536 if usize::is_power_of_two(alignment) => Ok(alignment),
+        _ => bail!("row alignment must be a power of two no larger than 65536"),
+    }
+}
 
-fn store_r32f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_f32::<LE>(pixel.r as f32)?;
+fn parse_palette_size(input: &str) -> Result<usize, Error> {
+    match input.parse()? {
+        colors @ 1..=256 => Ok(colors),
+        _ => bail!("palette size must be between 1 and 256"),
     }
+}
 
-    Ok(())
+fn parse_tone_mapping(input: &str) -> Result<ToneMapping, Error> {
+    match input {
+        "reinhard" => Ok(ToneMapping::Reinhard),
+        "aces" => Ok(ToneMapping::ACES),
+        _ => bail!("unknown tone mapping operator {}", input),
+    }
 }
 
-fn store_rg32f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_f32::<LE>(pixel.r as f32)?;
-        writer.write_f32::<LE>(pixel.g as f32)?;
+fn parse_header_format(input: &str) -> Result<HeaderFormat, Error> {
+    match input {
+        "binary" => Ok(HeaderFormat::Binary),
+        "json" => Ok(HeaderFormat::JSON),
+        _ => bail!("unknown header format {}", input),
     }
+}
 
-    Ok(())
+fn parse_alpha_policy(input: &str) -> Result<AlphaPolicy, Error> {
+    match input {
+        "opaque" => Ok(AlphaPolicy::Opaque),
+        "zero" => Ok(AlphaPolicy::Zero),
+        "keep" => Ok(AlphaPolicy::Keep),
+        _ => bail!("unknown alpha policy {}", input),
+    }
 }
 
-fn store_rgba32f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_f32::<LE>(pixel.r as f32)?;
-        writer.write_f32::<LE>(pixel.g as f32)?;
-        writer.write_f32::<LE>(pixel.b as f32)?;
-        writer.write_f32::<LE>(pixel.a as f32)?;
+fn parse_alpha_transfer(input: &str) -> Result<AlphaTransfer, Error> {
+    match input {
+        "linear" => Ok(AlphaTransfer::Linear),
+        "color" => Ok(AlphaTransfer::Color),
+        _ => bail!("unknown alpha transfer {}", input),
     }
+}
 
-    Ok(())
+fn parse_rounding(input: &str) -> Result<Rounding, Error> {
+    match input {
+        "truncate" => Ok(Rounding::Truncate),
+        "round" => Ok(Rounding::Round),
+        "round-even" => Ok(Rounding::RoundEven),
+        _ => bail!("unknown rounding mode {}", input),
+    }
 }
 
-fn store_r8_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    let row_padding = (4 - image.width % 4) % 4;
+fn parse_swizzle(input: &str) -> Result<Swizzle, Error> {
+    if input.is_empty() || input.len() > 4 {
+        bail!("expected a swizzle of 1 to 4 channels, got {}", input);
+    }
 
-    for y in 0..image.height {
-        for x in 0..image.width {
-            let pixel = image.pixels[(y * image.width + x) as usize];
+    let channels = input
+        .chars()
+        .map(|c| match c {
+            'r' => Ok(Channel::R),
+            'g' => Ok(Channel::G),
+            'b' => Ok(Channel::B),
+            'a' => Ok(Channel::A),
+            '0' => Ok(Channel::Zero),
+            '1' => Ok(Channel::One),
+            _ => bail!(
+                "unknown swizzle channel {}, expected one of r, g, b, a, 0, 1",
+                c
+            ),
+        })
+        .collect::<Result<_, Error>>()?;
 
-            writer.write_u8((pixel.r.min(1.0).max(0.0) * 255.0) as u8)?;
-        }
+    Ok(Swizzle { channels })
+}
 
-        for _ in 0..row_padding {
-            writer.write_u8(0)?;
-        }
+// Selected channels are kept and the others are cleared, with alpha being set
+// to one, which is expressed as a swizzle over all four channels.
+fn parse_channels(input: &str) -> Result<Swizzle, Error> {
+    if input.is_empty() || input.chars().any(|c| !"rgba".contains(c)) {
+        bail!("expected channels among r, g, b, a, got {}", input);
     }
 
-    Ok(())
+    let select = |c, channel, default| {
+        if input.contains(c) {
+            channel
+        } else {
+            default
+        }
+    };
+
+    let channels = vec![
+        select('r', Channel::R, Channel::Zero),
+        select('g', Channel::G, Channel::Zero),
+        select('b', Channel::B, Channel::Zero),
+        select('a', Channel::A, Channel::One),
+    ];
+
+    Ok(Swizzle { channels })
 }
 
-fn store_packed_r8_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_u8((pixel.r.min(1.0).max(0.0) * 255.0) as u8)?;
+fn parse_gamma(input: &str) -> Result<f64, Error> {
+    let gamma: f64 = input.parse()?;
+
+    if !(gamma.is_finite() && gamma > 0.0) {
+        bail!("gamma must be a positive number");
     }
 
-    Ok(())
+    Ok(gamma)
 }
 
-fn safe_f64_to_f16(x: f64) -> f16 {
-    f16::from_f64(x.max(-65504.0).min(65504.0))
+// The weights are normalized so that they sum to one, which preserves the value
+// of gray pixels.
+fn parse_luma_weights(input: &str) -> Result<[f64; 3], Error> {
+    let weights = input
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    match weights[..] {
+        [r, g, b] if [r, g, b].iter().all(|w| w.is_finite()) && r + g + b > 0.0 => {
+            Ok([r, g, b].map(|w| w / (r + g + b)))
+        }
+        _ => bail!("expected three weights with a positive sum, got {}", input),
+    }
+}
+
+fn parse_byte_order(input: &str) -> Result<Endianness, Error> {
+    match input {
+        "le" => Ok(Endianness::Little),
+        "be" => Ok(Endianness::Big),
+        _ => bail!("unknown byte order {}", input),
+    }
 }
 
-fn store_r16f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    let row_padding = image.width % 2;
+// Errors that scripts may want to tell apart are reported with a distinct exit
+// code. Any other error, e.g. an I/O error, is reported with exit code 1.
+#[derive(Debug, ThisError)]
+pub enum ConvertError {
+    #[error("{0}")]
+    InvalidArguments(String),
+    #[error("{0}")]
+    UnsupportedCombination(String),
+    #[error("non-color {0} requires non-color {1}")]
+    NonColorMismatch(&'static str, &'static str),
+    #[error("unsupported file type: {0}")]
+    UnsupportedFileType(String),
+    #[error("{0}")]
+    InvalidImage(String),
+    #[error("{0}")]
+    InvalidHeader(&'static str),
+    #[error("checksum mismatch, expected {expected:08x} but computed {computed:08x}")]
+    ChecksumMismatch { expected: u32, computed: u32 },
+    #[error("failed to convert {failed} of {total} files")]
+    BatchFailed { failed: usize, total: usize },
+    #[error("{count} pixels would be clamped when stored as {data_format:?}")]
+    ClampedPixels {
+        count: usize,
+        data_format: DataFormat,
+    },
+}
 
-    for y in 0..image.height {
-        for x in 0..image.width {
-            let pixel = image.pixels[(y * image.width + x) as usize];
+const USAGE_EXIT_CODE: i32 = 2;
 
-            writer.write_u16::<LE>(safe_f64_to_f16(pixel.r).to_bits())?;
+impl ConvertError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::InvalidArguments(_) => USAGE_EXIT_CODE,
+            Self::UnsupportedCombination(_) => 3,
+            Self::NonColorMismatch(_, _) => 4,
+            Self::UnsupportedFileType(_) => 5,
+            Self::InvalidImage(_) => 6,
+            Self::InvalidHeader(_) => 7,
+            Self::ChecksumMismatch { .. } => 8,
+            Self::BatchFailed { .. } => 9,
+            Self::ClampedPixels { .. } => 10,
         }
+    }
+}
 
-        for _ in 0..row_padding {
-            writer.write_u16::<LE>(0)?;
+fn main() {
+    // Invalid command-line arguments are usage errors, but help and version
+    // requests are printed to standard output and exit successfully.
+    let args = match Arguments::from_iter_safe(std::env::args_os()) {
+        Ok(args) => args,
+        Err(error) if error.use_stderr() => {
+            eprintln!("{}", error.message);
+            exit(USAGE_EXIT_CODE);
         }
+        Err(error) => error.exit(),
+    };
+
+    if let Err(error) = run(&args) {
+        let exit_code = error
+            .downcast_ref::<ConvertError>()
+            .map_or(1, ConvertError::exit_code);
+
+        eprintln!("Error: {:?}", ExitFailure::from(error));
+        exit(exit_code);
     }
+}
 
-    Ok(())
+// The conversion runs in a pool of its own rather than in the global pool, which
+// can only be configured once per process.
+fn run(args: &Arguments) -> Result<(), Error> {
+    ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()?
+        .install(|| run_in_pool(args))
 }
 
-fn store_rg16f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.r).to_bits())?;
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.g).to_bits())?;
+fn run_in_pool(args: &Arguments) -> Result<(), Error> {
+    if args.list_formats {
+        list_formats();
+        return Ok(());
     }
 
-    Ok(())
-}
+    if let Some(info_file) = &args.info {
+        return print_info(info_file);
+    }
 
-fn store_rgba16f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.r).to_bits())?;
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.g).to_bits())?;
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.b).to_bits())?;
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.a).to_bits())?;
+    if let Some(verify_file) = &args.verify {
+        return verify_checksum(args, verify_file);
     }
 
-    Ok(())
-}
+    if let Some(output_dir) = &args.output_dir {
+        return convert_batch(args, output_dir);
+    }
 
-fn store_packed_r16f_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_u16::<LE>(safe_f64_to_f16(pixel.r).to_bits())?;
+    if args.layers {
+        return match args.files.split_last() {
+            Some((output_file, source_files)) if !source_files.is_empty() => {
+                convert_layers(args, source_files, output_file)
+            }
+            _ => Err(ConvertError::InvalidArguments(
+                "expected one or more source files and an output file".to_owned(),
+            )
+            .into()),
+        };
     }
 
-    Ok(())
+    match args.files.as_slice() {
+        [source_file, output_file] if args.retag => retag(args, source_file, output_file),
+        [source_file, output_file] => convert(args, source_file, output_file),
+        _ => Err(ConvertError::InvalidArguments(
+            "expected a source and an output file, or an output directory".to_owned(),
+        )
+        .into()),
+    }
 }
 
-fn store_rgbe8_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        let v = pixel.r.max(pixel.g).max(pixel.b);
+fn convert_batch(args: &Arguments, output_dir: &Path) -> Result<(), Error> {
+    if args.files.iter().any(|file| file == Path::new("-")) {
+        return Err(ConvertError::InvalidArguments(
+            "cannot read from standard input in batch mode".to_owned(),
+        )
+        .into());
+    }
 
-        if v < 1e-32 {
-            writer.write_u8(0)?;
-            writer.write_u8(0)?;
-            writer.write_u8(0)?;
-            writer.write_u8(0)?;
-        } else {
-            let (f, e) = frexp(v);
+    let failures: Vec<_> = args
+        .files
+        .par_iter()
+        .filter_map(|source_file| {
+            let result = match source_file.file_stem() {
+                Some(stem) => {
+                    let mut file_name = stem.to_owned();
+                    file_name.push(".raw");
 
-            let r_byte = (pixel.r * f * 256.0 / v).min(255.0).max(0.0) as u8;
-            let g_byte = (pixel.g * f * 256.0 / v).min(255.0).max(0.0) as u8;
-            let b_byte = (pixel.b * f * 256.0 / v).min(255.0).max(0.0) as u8;
+                    convert(args, source_file, &output_dir.join(file_name))
+                }
+                None => Err(ConvertError::InvalidArguments(
+                    "source file has no file name".to_owned(),
+                )
+                .into()),
+            };
 
-            writer.write_u8(r_byte)?;
-            writer.write_u8(g_byte)?;
-            writer.write_u8(b_byte)?;
-            writer.write_u8((e + 128).max(0).min(255) as u8)?;
+            Some((source_file, result.err()?))
+        })
+        .collect();
+
+    for (source_file, error) in &failures {
+        eprintln!("{}: {}", source_file.display(), error);
+    }
+
+    if !failures.is_empty() {
+        return Err(ConvertError::BatchFailed {
+            failed: failures.len(),
+            total: args.files.len(),
         }
+        .into());
     }
 
     Ok(())
 }
 
-// https://stackoverflow.com/a/55696477/10471467
-fn frexp(s: f64) -> (f64, i32) {
-    if 0.0 == s {
-        (s, 0)
+fn convert(args: &Arguments, source_file: &Path, output_file: &Path) -> Result<(), Error> {
+    let mut timer = Instant::now();
+
+    let Layer {
+        color_space: output_color_space,
+        data_format: output_data_format,
+        mut levels,
+    } = match convert_layer(args, source_file, output_file, &mut timer)? {
+        Some(layer) => layer,
+        None => return Ok(()),
+    };
+
+    // Palette indices are non-color data, unlike the palette colors themselves.
+    let stored_color_space = match args.palette {
+        Some(_) => ColorSpace::NonColor,
+        None => output_color_space,
+    };
+
+    let palette = match (args.palette, palette_capacity(output_data_format)) {
+        (Some(colors), Some(capacity)) => Some(quantize_image(
+            &mut levels[0],
+            colors,
+            capacity,
+            args.rounding,
+        )?),
+        _ => None,
+    };
+
+    // The alpha channel is split out into its own image when it is stored in a
+    // separate file, and the main output is made opaque instead.
+    let alpha_levels: Vec<_> = if args.alpha_out.is_some() {
+        levels.iter_mut().map(Image::split_alpha).collect()
     } else {
-        let lg = s.abs().log2();
-        let x = (lg - lg.floor() - 1.0).exp2();
-        let exp = lg.floor() + 1.0;
-        (s.signum() * x, exp as i32)
+        vec![]
+    };
+
+    if args.verbose {
+        eprintln!("convert: {:.2?}", timer.elapsed());
+        timer = Instant::now();
     }
-}
 
-fn store_rgba8_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    for pixel in &image.pixels {
-        writer.write_u8((pixel.r.min(1.0).max(0.0) * 255.0) as u8)?;
-        writer.write_u8((pixel.g.min(1.0).max(0.0) * 255.0) as u8)?;
-        writer.write_u8((pixel.b.min(1.0).max(0.0) * 255.0) as u8)?;
-        writer.write_u8((pixel.a.min(1.0).max(0.0) * 255.0) as u8)?;
+    if args.preallocate {
+        write_output_in_place(
+            args,
+            output_file,
+            stored_color_space,
+            output_data_format,
+            &levels,
+        )?;
+    } else {
+        write_output(
+            args,
+            output_file,
+            stored_color_space,
+            output_data_format,
+            &levels,
+            1,
+        )?;
     }
 
-    Ok(())
-}
+    if let Some(alpha_file) = &args.alpha_out {
+        write_alpha_output(args, alpha_file, output_data_format, &alpha_levels)?;
+    }
 
-fn store_bc1_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    if image.width % 4 != 0 || image.height % 4 != 0 {
-        bail!("BC1: image dimensions must be a multiple of 4");
+    if let (Some(palette_file), Some(palette)) = (&args.palette_out, &palette) {
+        write_palette_output(args, palette_file, output_color_space, palette)?;
     }
 
-    let mut rgba = Vec::with_capacity(image.width as usize * image.height as usize * 4);
+    if args.verbose {
+        eprintln!("store: {:.2?}", timer.elapsed());
+    }
 
-    for pixel in &image.pixels {
-        rgba.push((pixel.r.min(1.0).max(0.0) * 255.0) as u8);
-        rgba.push((pixel.g.min(1.0).max(0.0) * 255.0) as u8);
-        rgba.push((pixel.b.min(1.0).max(0.0) * 255.0) as u8);
-        rgba.push(255);
+    if !args.quiet {
+        eprintln!(
+            "{:?} {:?} {} {}",
+            stored_color_space, output_data_format, levels[0].width, levels[0].height
+        );
     }
 
-    let mut compressed =
-        vec![0; Format::Bc1.compressed_size(image.width as usize, image.height as usize)];
+    Ok(())
+}
 
-    Format::Bc1.compress(
-        &rgba,
-        image.width as usize,
-        image.height as usize,
-        squish::Params {
-            algorithm: Algorithm::IterativeClusterFit,
-            weights: squish::COLOUR_WEIGHTS_PERCEPTUAL,
-            weigh_colour_by_alpha: false,
-        },
-        &mut compressed,
-    );
+// Each source file is converted in turn into one layer of the output, and the
+// layers are stored one after the other. They must all have the same size.
+fn convert_layers(
+    args: &Arguments,
+    source_files: &[PathBuf],
+    output_file: &Path,
+) -> Result<(), Error> {
+    let mut timer = Instant::now();
 
-    Ok(writer.write_all(&compressed)?)
-}
+    let mut layers: Vec<Layer> = vec![];
 
-fn store_rg8_pixels<W: Write>(image: &Image, mut writer: W) -> Result<(), Error> {
-    let row_padding = (image.width % 2) * 2;
+    for source_file in source_files {
+        let layer = match convert_layer(args, source_file, output_file, &mut timer)? {
+            Some(layer) => layer,
+            None => return Ok(()),
+        };
 
-    for y in 0..image.height {
-        for x in 0..image.width {
-            let pixel = image.pixels[(y * image.width + x) as usize];
+        if let Some(first) = layers.first() {
+            let (width, height) = (first.levels[0].width, first.levels[0].height);
 
-            writer.write_u8((pixel.r.min(1.0).max(0.0) * 255.0) as u8)?;
-            writer.write_u8((pixel.g.min(1.0).max(0.0) * 255.0) as u8)?;
+            if (layer.levels[0].width, layer.levels[0].height) != (width, height) {
+                return Err(ConvertError::InvalidImage(format!(
+                    "{}: layer dimensions {}x{} do not match the first layer's {}x{}",
+                    source_file.display(),
+                    layer.levels[0].width,
+                    layer.levels[0].height,
+                    width,
+                    height
+                ))
+                .into());
+            }
         }
 
-        for _ in 0..row_padding {
-            writer.write_u8(0)?;
-        }
+        layers.push(layer);
     }
 
-    Ok(())
+    if args.verbose {
+        eprintln!("convert: {:.2?}", timer.elapsed());
+        timer = Instant::now();
+    }
+
+    let layer_count = layers.len() as u32;
+
+    let (color_space, data_format) = (layers[0].color_space, layers[0].data_format);
+    let levels: Vec<_> = layers.into_iter().flat_map(|layer| layer.levels).collect();
+
+    write_output(
+        args,
+        output_file,
+        color_space,
+        data_format,
+        &levels,
+        layer_count,
+    )?;
+
+    if args.verbose {
+        eprintln!("store: {:.2?}", timer.elapsed());
+    }
+
+    if !args.quiet {
+        eprintln!(
+            "{:?} {:?} {} {} ({} layers)",
+            color_space, data_format, levels[0].width, levels[0].height, layer_count
+        );
+    }
+
+    Ok(())
+}
+
+fn check_combination(
+    source_color_space: ColorSpace,
+    color_space: ColorSpace,
+    data_format: DataFormat,
+) -> Result<(), Error> {
+    let result = is_valid_conversion(source_color_space, color_space)
+        .and_then(|()| is_valid_combination(color_space, data_format));
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(CombinationError::SourceOnlyColorSpace) => Err(ConvertError::UnsupportedCombination(
+            format!("{} is only supported as a source color space", color_space),
+        )
+        .into()),
+        Err(CombinationError::RequiresSRGB) => Err(ConvertError::UnsupportedCombination(format!(
+            "{}: output color space must be SRGB or LinearSRGB",
+            data_format
+        ))
+        .into()),
+        Err(CombinationError::NonColorSource) => {
+            Err(ConvertError::NonColorMismatch("source", "output").into())
+        }
+        Err(CombinationError::NonColorOutput) => {
+            Err(ConvertError::NonColorMismatch("output", "source").into())
+        }
+        Err(CombinationError::RequiresLuminance) => {
+            Err(ConvertError::UnsupportedCombination(format!(
+                "{}: {} has no luminance channel to store",
+                data_format, color_space
+            ))
+            .into())
+        }
+    }
+}
+
+// Only the header is rewritten, and everything following it is copied through
+// unchanged, so the data format can only be replaced by one with the same pixel
+// layout. The checksum remains valid since the pixel data is not modified.
+fn retag(args: &Arguments, source_file: &Path, output_file: &Path) -> Result<(), Error> {
+    if args.output_color_space.is_none() && args.output_data_format.is_none() {
+        return Err(ConvertError::InvalidArguments(
+            "expected an output color space or a format to retag with".to_owned(),
+        )
+        .into());
+    }
+
+    let bytes = read_source(source_file)?;
+    let raw_image = parse_raw_image(&bytes)?;
+
+    let color_space = args.output_color_space.unwrap_or(raw_image.color_space());
+    let data_format = args.output_data_format.unwrap_or(raw_image.data_format());
+
+    check_combination(color_space, color_space, data_format)?;
+
+    let source_format = raw_image.data_format();
+
+    let layout = |format: DataFormat| {
+        (
+            format.channel_count(),
+            format.bits_per_pixel(),
+            format.row_alignment(),
+        )
+    };
+
+    if layout(data_format) != layout(source_format) {
+        return Err(ConvertError::UnsupportedCombination(format!(
+            "{}: cannot be retagged as {}, which has a different pixel layout",
+            source_format, data_format
+        ))
+        .into());
+    }
+
+    let mut header = *raw_image.header();
+    header.color_space = color_space.into();
+    header.data_format = data_format.into();
+
+    let mut file = BufWriter::new(create_output(output_file)?);
+
+    file.write_all(header.as_bytes())?;
+    file.write_all(&bytes[size_of::<Header>()..])?;
+    file.flush()?;
+
+    if !args.quiet {
+        eprintln!(
+            "{:?} {:?} {} {}",
+            color_space,
+            data_format,
+            raw_image.width(),
+            raw_image.height()
+        );
+    }
+
+    Ok(())
+}
+
+// A source image converted into the output color space, ready to be stored.
+struct Layer {
+    color_space: ColorSpace,
+    data_format: DataFormat,
+    levels: Vec<Image>,
+}
+
+// Returns `None` if the output was already written while converting, which is
+// the case when converting in strips, or if nothing is to be written at all.
+fn convert_layer(
+    args: &Arguments,
+    source_file: &Path,
+    output_file: &Path,
+    timer: &mut Instant,
+) -> Result<Option<Layer>, Error> {
+    let bytes = read_source(source_file)?;
+
+    let raw_image = if args.transcode {
+        Some(parse_raw_image(&bytes)?)
+    } else {
+        None
+    };
+
+    let source_color_space = match &raw_image {
+        Some(raw_image) => Some(raw_image.color_space()),
+        None => match args.source_color_space {
+            Some(SourceColorSpace::Auto) => Some(detect_color_space(&bytes)),
+            Some(SourceColorSpace::Known(color_space)) => Some(color_space),
+            None => None,
+        },
+    };
+
+    // Pixel data is repackaged as-is when passing through, so both sides are
+    // treated as non-color data and no color conversion is ever performed.
+    let (source_color_space, output_color_space) = if args.passthrough {
+        (Some(ColorSpace::NonColor), Some(ColorSpace::NonColor))
+    } else {
+        (source_color_space, args.output_color_space)
+    };
+
+    let (source_color_space, output_color_space, output_data_format) = match (
+        source_color_space,
+        output_color_space,
+        args.output_data_format,
+    ) {
+        (Some(source), Some(output), Some(format)) => (source, output, format),
+        _ => {
+            return Err(ConvertError::InvalidArguments(
+                "expected a source color space, an output color space and a format".to_owned(),
+            )
+            .into())
+        }
+    };
+
+    check_combination(source_color_space, output_color_space, output_data_format)?;
+
+    if let Some(swizzle) = &args.swizzle {
+        if swizzle.channels.len() > output_data_format.channel_count() {
+            return Err(ConvertError::UnsupportedCombination(format!(
+                "{:?}: swizzle has more than {} channels",
+                output_data_format,
+                output_data_format.channel_count()
+            ))
+            .into());
+        }
+    }
+
+    if let Some(colors) = args.palette {
+        match palette_capacity(output_data_format) {
+            Some(capacity) if colors <= capacity => {}
+            Some(capacity) => {
+                return Err(ConvertError::UnsupportedCombination(format!(
+                    "{}: cannot index more than {} palette colors",
+                    output_data_format, capacity
+                ))
+                .into())
+            }
+            None => {
+                return Err(ConvertError::UnsupportedCombination(format!(
+                    "{}: palette indices must be stored as R8, PackedR8, PackedR4 or PackedR1",
+                    output_data_format
+                ))
+                .into())
+            }
+        }
+    }
+
+    if args.alpha_out.as_deref() == Some(Path::new("-")) && output_file == Path::new("-") {
+        return Err(ConvertError::InvalidArguments(
+            "cannot write both the output and the alpha to standard output".to_owned(),
+        )
+        .into());
+    }
+
+    if args.row_align.is_some() && output_data_format == DataFormat::BC1 {
+        return Err(ConvertError::UnsupportedCombination(
+            "BC1: row alignment cannot be overridden".to_owned(),
+        )
+        .into());
+    }
+
+    if args.mips && output_data_format == DataFormat::BC1 {
+        return Err(ConvertError::UnsupportedCombination(
+            "BC1: mip levels are not supported".to_owned(),
+        )
+        .into());
+    }
+
+    if args.supersample.is_some() && source_color_space == ColorSpace::HSV {
+        return Err(ConvertError::UnsupportedCombination(
+            "HSV: supersampling is not supported".to_owned(),
+        )
+        .into());
+    }
+
+    if args.reconstruct_z {
+        if output_data_format.channel_count() != 2 {
+            return Err(ConvertError::UnsupportedCombination(format!(
+                "{}: reconstructing Z requires a two-channel format",
+                output_data_format
+            ))
+            .into());
+        }
+
+        if output_color_space != ColorSpace::NonColor {
+            return Err(ConvertError::UnsupportedCombination(
+                "reconstructing Z requires NonColor normals".to_owned(),
+            )
+            .into());
+        }
+    }
+
+    if args.normalize && source_color_space == ColorSpace::HSV {
+        return Err(ConvertError::UnsupportedCombination(
+            "HSV: normalization is not supported".to_owned(),
+        )
+        .into());
+    }
+
+    if args.premultiply
+        && !is_single_channel_format(output_data_format)
+        && output_color_space == ColorSpace::NonColor
+    {
+        eprintln!("warning: premultiplying alpha of non-color data");
+    }
+
+    if let Some(strip_height) = args.strip_height {
+        if strip_height == 0 {
+            return Err(
+                ConvertError::InvalidArguments("strip height must be nonzero".to_owned()).into(),
+            );
+        }
+
+        let source = StripSource::new(&bytes, raw_image.as_ref())?;
+
+        convert_strips(
+            args,
+            source,
+            output_file,
+            strip_height,
+            source_color_space,
+            output_color_space,
+            output_data_format,
+        )?;
+
+        return Ok(None);
+    }
+
+    let luma_only = is_single_channel_format(output_data_format);
+
+    // The WebP decoder only decodes the luma plane, so this is rejected before
+    // the image is decoded rather than silently dropping its chroma.
+    if raw_image.is_none() && !luma_only && matches!(guess_format(&bytes), Ok(ImageFormat::WEBP)) {
+        return Err(ConvertError::UnsupportedCombination(format!(
+            "{}: WebP images can only be stored in single-channel formats",
+            output_data_format
+        ))
+        .into());
+    }
+
+    if let Some(raw_image) = &raw_image {
+        if is_half_float_copy(args, raw_image, output_color_space, output_data_format) {
+            copy_half_floats(args, raw_image, output_file)?;
+            return Ok(None);
+        }
+    }
+
+    // Single-channel raw images are decoded into the red channel only, just like
+    // grayscale images loaded for a single-channel format.
+    let (mut image, grayscale) = match &raw_image {
+        Some(raw_image) => (
+            load_raw_image(raw_image, args.max_pixels)?,
+            raw_image.data_format().channel_count() == 1,
+        ),
+        None => load_image(&bytes, luma_only, args.frame.unwrap_or(0), args.max_pixels)?,
+    };
+
+    if image.width == 0 || image.height == 0 {
+        return Err(
+            ConvertError::InvalidImage("source image has zero dimensions".to_owned()).into(),
+        );
+    }
+
+    if args.verbose {
+        eprintln!("load: {:.2?}", timer.elapsed());
+        *timer = Instant::now();
+    }
+
+    unpremultiply_source(args, &mut image, source_color_space);
+
+    if let Some(mask_file) = &args.alpha_from {
+        apply_alpha_mask(args, &mut image, mask_file)?;
+    }
+
+    apply_alpha_policy(args, &mut image);
+
+    if let Some((width, height)) = args.resize {
+        if width == 0 || height == 0 {
+            return Err(ConvertError::InvalidArguments(
+                "resize dimensions must be nonzero".to_owned(),
+            )
+            .into());
+        }
+
+        check_pixel_count(width, height, args.max_pixels)?;
+
+        image = match args.supersample {
+            Some(samples) => supersample_image(
+                &image,
+                width,
+                height,
+                samples,
+                source_color_space,
+                args.gamma,
+            )?,
+            None => resize_image(
+                &image,
+                width,
+                height,
+                args.resize_filter,
+                source_color_space,
+                args.gamma,
+            )?,
+        };
+    }
+
+    if args.dry_run {
+        let size = output_size(args, output_data_format, image.width, image.height)?;
+        println!("{}: {} bytes", output_file.display(), size);
+        return Ok(None);
+    }
+
+    if args.normalize {
+        let max = normalize_image(&mut image, source_color_space, args.gamma);
+
+        if !args.quiet {
+            eprintln!("{}: normalized by {}", source_file.display(), max);
+        }
+    }
+
+    // Palette colors are stored as RGBA8, so the pixels are processed as such
+    // before being quantized, and only their indices are in the output format.
+    let color_data_format = match args.palette {
+        Some(_) => DataFormat::RGBA8,
+        None => output_data_format,
+    };
+
+    process_pixels(
+        args,
+        &mut image,
+        grayscale,
+        source_color_space,
+        output_color_space,
+        color_data_format,
+    )?;
+
+    let mut levels = vec![image];
+
+    if args.mips {
+        levels.extend(generate_mip_levels(
+            &levels[0],
+            output_color_space,
+            args.gamma,
+        ));
+    }
+
+    for level in &mut levels {
+        finish_level(args, level, output_color_space, color_data_format);
+    }
+
+    if args.strict {
+        let count = levels
+            .iter()
+            .map(|level| count_clamped_pixels(level, color_data_format))
+            .sum();
+
+        if count > 0 {
+            return Err(ConvertError::ClampedPixels {
+                count,
+                data_format: output_data_format,
+            }
+            .into());
+        }
+    }
+
+    Ok(Some(Layer {
+        color_space: output_color_space,
+        data_format: output_data_format,
+        levels,
+    }))
+}
+
+// Images are converted a strip of rows at a time, with each strip stored before
+// the next one is decoded, so only one strip of pixels is ever held in memory.
+// This rules out any operation which needs to see the whole image at once.
+fn convert_strips(
+    args: &Arguments,
+    mut source: StripSource,
+    output_file: &Path,
+    strip_height: u32,
+    source_color_space: ColorSpace,
+    output_color_space: ColorSpace,
+    output_data_format: DataFormat,
+) -> Result<(), Error> {
+    let timer = Instant::now();
+
+    if output_data_format == DataFormat::BC1 {
+        return Err(ConvertError::UnsupportedCombination(
+            "BC1: cannot be converted in strips".to_owned(),
+        )
+        .into());
+    }
+
+    let (width, height) = source.dimensions();
+
+    if width == 0 || height == 0 {
+        return Err(
+            ConvertError::InvalidImage("source image has zero dimensions".to_owned()).into(),
+        );
+    }
+
+    check_pixel_count(width, height, args.max_pixels)?;
+
+    if args.dry_run {
+        let size = output_size(args, output_data_format, width, height)?;
+        println!("{}: {} bytes", output_file.display(), size);
+        return Ok(());
+    }
+
+    let mut file = if output_file == Path::new("-") {
+        if args.header {
+            return Err(ConvertError::InvalidArguments(
+                "cannot embed a header when writing strips to standard output".to_owned(),
+            )
+            .into());
+        }
+
+        None
+    } else {
+        Some(File::create(output_file)?)
+    };
+
+    let checksum = match &mut file {
+        Some(file) => {
+            if args.header {
+                file.seek(SeekFrom::Start(size_of::<Header>() as u64))?;
+            }
+
+            write_strips(
+                args,
+                &mut source,
+                strip_height,
+                source_color_space,
+                output_color_space,
+                output_data_format,
+                file,
+            )?
+        }
+        None => write_strips(
+            args,
+            &mut source,
+            strip_height,
+            source_color_space,
+            output_color_space,
+            output_data_format,
+            stdout().lock(),
+        )?,
+    };
+
+    if args.header || args.header_file.is_some() {
+        let header = build_header(
+            output_color_space,
+            output_data_format,
+            (width, height),
+            1,
+            output_flags(args, output_data_format),
+            checksum,
+        )?;
+
+        if let Some(header_file) = &args.header_file {
+            write_header_file(args, header_file, &header)?;
+        } else if let Some(file) = &mut file {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(header.as_bytes())?;
+        }
+    }
+
+    if args.verbose {
+        eprintln!("convert: {:.2?}", timer.elapsed());
+    }
+
+    if !args.quiet {
+        eprintln!(
+            "{:?} {:?} {} {}",
+            output_color_space, output_data_format, width, height
+        );
+    }
+
+    Ok(())
+}
+
+// Returns the checksum of the pixel data, which is followed by the metadata.
+fn write_strips<W: Write>(
+    args: &Arguments,
+    source: &mut StripSource,
+    strip_height: u32,
+    source_color_space: ColorSpace,
+    output_color_space: ColorSpace,
+    output_data_format: DataFormat,
+    mut output: W,
+) -> Result<u32, Error> {
+    let (width, height) = source.dimensions();
+
+    let mut writer = ChecksumWriter::new(BufWriter::new(&mut output));
+    let mut clamped_pixels = 0;
+
+    let grayscale = source.is_grayscale();
+
+    for y in (0..height).step_by(strip_height as usize) {
+        let mut strip = Image::new(width, strip_height.min(height - y))?;
+
+        source.read_strip(y, &mut strip)?;
+
+        unpremultiply_source(args, &mut strip, source_color_space);
+        apply_alpha_policy(args, &mut strip);
+
+        process_pixels(
+            args,
+            &mut strip,
+            grayscale,
+            source_color_space,
+            output_color_space,
+            output_data_format,
+        )?;
+
+        finish_level(args, &mut strip, output_color_space, output_data_format);
+
+        if args.strict {
+            clamped_pixels += count_clamped_pixels(&strip, output_data_format);
+        }
+
+        store_pixels(
+            &strip,
+            output_data_format,
+            args.row_align,
+            args.byte_order,
+            args.rounding,
+            &mut writer,
+        )?;
+    }
+
+    let checksum = writer.finish()?;
+
+    output.write_all(&metadata_trailer(args)?)?;
+    output.flush()?;
+
+    // The strips are already stored by the time all of them have been checked.
+    if clamped_pixels > 0 {
+        return Err(ConvertError::ClampedPixels {
+            count: clamped_pixels,
+            data_format: output_data_format,
+        }
+        .into());
+    }
+
+    Ok(checksum)
+}
+
+// Source pixels with premultiplied alpha are made straight before anything else
+// is done with them, since the alpha policy may then replace the alpha channel.
+fn unpremultiply_source(args: &Arguments, image: &mut Image, source_color_space: ColorSpace) {
+    if args.source_premultiplied {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.unpremultiply(source_color_space, args.gamma);
+        });
+    }
+}
+
+// The mask is stored as-is in the alpha channel, which is never color-converted,
+// so its color space is irrelevant. Its red channel is used if it has several.
+fn apply_alpha_mask(args: &Arguments, image: &mut Image, mask_file: &Path) -> Result<(), Error> {
+    let (mask, _) = load_image(&read_source(mask_file)?, true, 0, args.max_pixels)?;
+
+    if (mask.width, mask.height) != (image.width, image.height) {
+        return Err(ConvertError::InvalidImage(format!(
+            "{}: alpha mask dimensions {}x{} do not match the source's {}x{}",
+            mask_file.display(),
+            mask.width,
+            mask.height,
+            image.width,
+            image.height
+        ))
+        .into());
+    }
+
+    image
+        .pixels
+        .par_iter_mut()
+        .zip(&mask.pixels)
+        .for_each(|(pixel, mask)| {
+            pixel.a = mask.r;
+        });
+
+    Ok(())
+}
+
+fn apply_alpha_policy(args: &Arguments, image: &mut Image) {
+    if args.alpha != AlphaPolicy::Keep {
+        let alpha = if args.alpha == AlphaPolicy::Opaque {
+            1.0
+        } else {
+            0.0
+        };
+
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            pixel.a = alpha;
+        });
+    }
+}
+
+// The color channels are divided in linear light by their largest finite value,
+// which is returned, so that it can be multiplied back in to undo the scaling.
+// Images without any positive color value are left unchanged.
+fn normalize_image(image: &mut Image, color_space: ColorSpace, gamma: Option<f64>) -> f64 {
+    let max = image
+        .pixels
+        .par_iter()
+        .map(|pixel| {
+            let pixel = pixel.decode_transfer(color_space, gamma);
+
+            [pixel.r, pixel.g, pixel.b]
+                .iter()
+                .copied()
+                .filter(|x| x.is_finite())
+                .fold(0.0, f64::max)
+        })
+        .reduce(|| 0.0, f64::max);
+
+    if max <= 0.0 {
+        return 1.0;
+    }
+
+    image.pixels.par_iter_mut().for_each(|pixel| {
+        let mut linear = pixel.decode_transfer(color_space, gamma);
+
+        linear.r /= max;
+        linear.g /= max;
+        linear.b /= max;
+
+        *pixel = linear.encode_transfer(color_space, gamma);
+    });
+
+    max
+}
+
+// Pixels are processed independently of each other, so these steps can be done
+// on any part of the image, and are applied in the same order in either case.
+// Grayscale images only have a red channel, which already holds the luminance.
+fn process_pixels(
+    args: &Arguments,
+    image: &mut Image,
+    grayscale: bool,
+    source_color_space: ColorSpace,
+    output_color_space: ColorSpace,
+    output_data_format: DataFormat,
+) -> Result<(), Error> {
+    if source_color_space != output_color_space {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.convert_into_cie_xyz(source_color_space, args.gamma);
+            *pixel = pixel.convert_from_cie_xyz(output_color_space, args.gamma);
+        });
+
+        if args.alpha_transfer == AlphaTransfer::Color {
+            image.pixels.par_iter_mut().for_each(|pixel| {
+                *pixel = pixel.convert_alpha_transfer(
+                    source_color_space,
+                    output_color_space,
+                    args.gamma,
+                );
+            });
+        }
+    }
+
+    if args.reconstruct_z {
+        let signed = min_representable_value(output_data_format) < 0.0;
+
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.normal_xy(signed);
+        });
+    }
+
+    if args.exposure != 0.0 || args.tonemap.is_some() {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.tone_map(output_color_space, args.gamma, args.exposure, args.tonemap);
+        });
+    }
+
+    // Color is converted to luminance for single-channel formats, unless a swizzle
+    // explicitly selects which channel to store. Non-color data is stored as-is.
+    if is_single_channel_format(output_data_format)
+        && !grayscale
+        && args.swizzle.is_none()
+        && matches!(
+            output_color_space,
+            ColorSpace::SRGB | ColorSpace::LinearSRGB | ColorSpace::AdobeRGB
+        )
+    {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.luma(output_color_space, args.gamma, args.luma_weights);
+        });
+    }
+
+    if args.premultiply && !is_single_channel_format(output_data_format) {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.premultiply(output_color_space, args.gamma);
+        });
+    }
+
+    if args.clamp {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.clamp(0.0, 1.0);
+        });
+    }
+
+    if args.clamp_min.is_some() || args.clamp_max.is_some() {
+        let min = args
+            .clamp_min
+            .unwrap_or_else(|| min_representable_value(output_data_format));
+        let max = args
+            .clamp_max
+            .unwrap_or_else(|| max_representable_value(output_data_format));
+
+        if min.is_nan() || max.is_nan() || min > max {
+            return Err(ConvertError::InvalidArguments(format!(
+                "clamp range [{}, {}] is empty",
+                min, max
+            ))
+            .into());
+        }
+
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.clamp(min, max);
+        });
+    }
+
+    if args.sanitize {
+        let max = max_representable_value(output_data_format);
+
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.sanitize(max);
+        });
+    }
+
+    Ok(())
+}
+
+fn finish_level(
+    args: &Arguments,
+    level: &mut Image,
+    output_color_space: ColorSpace,
+    output_data_format: DataFormat,
+) {
+    // The sRGB formats are decoded by the sampler with the standard transfer
+    // function, so linear pixel data is encoded just before being stored.
+    if output_data_format == DataFormat::SRGBA8 && output_color_space == ColorSpace::LinearSRGB {
+        level.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.encode_transfer(ColorSpace::SRGB, None);
+        });
+
+        if args.alpha_transfer == AlphaTransfer::Color {
+            level.pixels.par_iter_mut().for_each(|pixel| {
+                *pixel =
+                    pixel.convert_alpha_transfer(ColorSpace::LinearSRGB, ColorSpace::SRGB, None);
+            });
+        }
+    }
+
+    if let Some(swizzle) = args.swizzle.as_ref().or(args.channels.as_ref()) {
+        level.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.swizzle(swizzle);
+        });
+    }
+
+    if args.flip_y {
+        level.flip_vertically();
+    }
+
+    if args.dither && is_unorm8_format(output_data_format) {
+        dither_pixels(level, args.rounding);
+    }
+}
+
+fn output_flags(args: &Arguments, data_format: DataFormat) -> u32 {
+    let mut flags = 0;
+
+    if args.byte_order == Endianness::Big {
+        flags |= Header::FLAG_BIG_ENDIAN;
+    }
+
+    if args.premultiply && !is_single_channel_format(data_format) {
+        flags |= Header::FLAG_PREMULTIPLIED_ALPHA;
+    }
+
+    if args.flip_y {
+        flags |= Header::FLAG_ORIGIN_BOTTOM_LEFT;
+    }
+
+    if args.mips {
+        flags |= Header::FLAG_MIPMAPPED;
+    }
+
+    if !args.metadata.is_empty() {
+        flags |= Header::FLAG_METADATA;
+    }
+
+    if let Some(row_alignment) = args.row_align {
+        flags |= (row_alignment.trailing_zeros() + 1) << Header::ROW_ALIGNMENT_SHIFT;
+    }
+
+    flags
+}
+
+fn metadata_trailer(args: &Arguments) -> Result<Vec<u8>, Error> {
+    let mut trailer = vec![];
+
+    if !args.metadata.is_empty() {
+        let pairs: Vec<_> = args
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+
+        Metadata::write(&pairs, &mut trailer)?;
+    }
+
+    Ok(trailer)
+}
+
+// The output file is sized up front and the pixel data is written directly
+// after the space reserved for the header, which is filled in at the end once
+// the checksum is known, so that the pixel data is never buffered in memory.
+fn write_output_in_place(
+    args: &Arguments,
+    output_file: &Path,
+    color_space: ColorSpace,
+    data_format: DataFormat,
+    levels: &[Image],
+) -> Result<(), Error> {
+    if output_file == Path::new("-") {
+        return Err(ConvertError::InvalidArguments(
+            "cannot preallocate standard output".to_owned(),
+        )
+        .into());
+    }
+
+    let size = output_size(args, data_format, levels[0].width, levels[0].height)?;
+
+    let mut file = File::create(output_file)?;
+    file.set_len(size)?;
+
+    if args.header {
+        file.seek(SeekFrom::Start(size_of::<Header>() as u64))?;
+    }
+
+    let mut writer = ChecksumWriter::new(BufWriter::new(&mut file));
+
+    for level in levels {
+        store_pixels(
+            level,
+            data_format,
+            args.row_align,
+            args.byte_order,
+            args.rounding,
+            &mut writer,
+        )?;
+    }
+
+    let checksum = writer.finish()?;
+
+    file.write_all(&metadata_trailer(args)?)?;
+
+    if args.header || args.header_file.is_some() {
+        let header = build_header(
+            color_space,
+            data_format,
+            (levels[0].width, levels[0].height),
+            1,
+            output_flags(args, data_format),
+            checksum,
+        )?;
+
+        if let Some(header_file) = &args.header_file {
+            write_header_file(args, header_file, &header)?;
+        } else {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(header.as_bytes())?;
+        }
+    }
+
+    if file.metadata()?.len() != size {
+        bail!(
+            "output size does not match the expected size of {} bytes",
+            size
+        );
+    }
+
+    Ok(())
+}
+
+fn build_header(
+    color_space: ColorSpace,
+    data_format: DataFormat,
+    (width, height): (u32, u32),
+    layers: u32,
+    flags: u32,
+    checksum: u32,
+) -> Result<Header, Error> {
+    Ok(HeaderBuilder::new()
+        .color_space(color_space)
+        .data_format(data_format)
+        .dimensions(width, height)
+        .layers(layers)
+        .checksum(checksum)
+        .flags(flags)
+        .tool_version(
+            env!("CARGO_PKG_VERSION_MAJOR").parse()?,
+            env!("CARGO_PKG_VERSION_MINOR").parse()?,
+            env!("CARGO_PKG_VERSION_PATCH").parse()?,
+        )
+        .timestamp(creation_timestamp()?)
+        .build())
+}
+
+fn write_output(
+    args: &Arguments,
+    output_file: &Path,
+    color_space: ColorSpace,
+    data_format: DataFormat,
+    levels: &[Image],
+    layers: u32,
+) -> Result<(), Error> {
+    write_pixel_data(
+        args,
+        output_file,
+        color_space,
+        data_format,
+        (levels[0].width, levels[0].height),
+        layers,
+        |writer| store_levels(args, data_format, levels, writer),
+    )
+}
+
+// The pixel data is written by the given function, which returns its checksum,
+// and is followed by the metadata trailer. The header is written along with it
+// or to its own file as requested.
+fn write_pixel_data(
+    args: &Arguments,
+    output_file: &Path,
+    color_space: ColorSpace,
+    data_format: DataFormat,
+    dimensions: (u32, u32),
+    layers: u32,
+    mut store: impl FnMut(&mut dyn Write) -> Result<u32, Error>,
+) -> Result<(), Error> {
+    let header = |checksum| {
+        build_header(
+            color_space,
+            data_format,
+            dimensions,
+            layers,
+            output_flags(args, data_format),
+            checksum,
+        )
+    };
+
+    let checksum = stream_output(
+        output_file,
+        args.header && args.header_file.is_none(),
+        |writer| {
+            let checksum = store(&mut *writer)?;
+            writer.write_all(&metadata_trailer(args)?)?;
+            Ok(checksum)
+        },
+        header,
+    )?;
+
+    if let Some(header_file) = &args.header_file {
+        write_header_file(args, header_file, &header(checksum)?)?;
+    }
+
+    Ok(())
+}
+
+fn write_header_file(args: &Arguments, header_file: &Path, header: &Header) -> Result<(), Error> {
+    let mut header_file = create_output(header_file)?;
+
+    match args.header_format.unwrap_or(HeaderFormat::Binary) {
+        HeaderFormat::Binary => header_file.write_all(header.as_bytes())?,
+        HeaderFormat::JSON => writeln!(
+            header_file,
+            r#"{{"color_space":"{}","data_format":"{}","width":{},"height":{},"checksum":{},"layers":{},"flags":{},"tool_version":{},"timestamp":{}}}"#,
+            header.color_space.parse_or(ColorSpace::NonColor),
+            header.data_format.parse_or(DataFormat::R32F),
+            header.dimensions[0],
+            header.dimensions[1],
+            header.checksum,
+            header.layer_count(),
+            header.flags,
+            header.tool_version,
+            header.timestamp
+        )?,
+    }
+
+    Ok(())
+}
+
+// The alpha file is stored as non-color data with the same bit depth as the
+// main output, i.e. in 8 bits for the fixed-point formats and as half-floats
+// otherwise, and only has a header if the main output has one embedded.
+fn write_alpha_output(
+    args: &Arguments,
+    alpha_file: &Path,
+    data_format: DataFormat,
+    levels: &[Image],
+) -> Result<(), Error> {
+    let data_format = if max_representable_value(data_format) == 1.0 {
+        DataFormat::R8
+    } else {
+        DataFormat::R16F
+    };
+
+    stream_output(
+        alpha_file,
+        args.header,
+        |writer| store_levels(args, data_format, levels, writer),
+        |checksum| {
+            build_header(
+                ColorSpace::NonColor,
+                data_format,
+                (levels[0].width, levels[0].height),
+                1,
+                output_flags(args, data_format) & !Header::FLAG_METADATA,
+                checksum,
+            )
+        },
+    )?;
+
+    Ok(())
+}
+
+// Half-float pixel data which is neither converted nor otherwise modified is
+// copied through bit for bit instead of going through f64 and back, so that NaN
+// payloads and infinities, which are not stored as-is otherwise, are preserved.
+fn is_half_float_copy(
+    args: &Arguments,
+    raw_image: &RawImage,
+    output_color_space: ColorSpace,
+    output_data_format: DataFormat,
+) -> bool {
+    let header = raw_image.header();
+
+    matches!(
+        output_data_format,
+        DataFormat::R16F | DataFormat::RG16F | DataFormat::RGBA16F
+    ) && raw_image.data_format() == output_data_format
+        && raw_image.color_space() == output_color_space
+        && header.byte_order() == Endianness::Little
+        && !header.is_premultiplied()
+        && !header.is_origin_bottom_left()
+        && !args.dry_run
+        && args.alpha_out.is_none()
+        && !args.flip_y
+        && args.resize.is_none()
+        && !args.mips
+        && !args.premultiply
+        && !args.clamp
+        && !args.sanitize
+        && args.exposure == 0.0
+        && args.tonemap.is_none()
+        && args.alpha == AlphaPolicy::Keep
+        && args.swizzle.is_none()
+        && args.channels.is_none()
+        && args.byte_order == Endianness::Little
+}
+
+// Only the base level is copied, like only the base level is ever transcoded.
+fn copy_half_floats(
+    args: &Arguments,
+    raw_image: &RawImage,
+    output_file: &Path,
+) -> Result<(), Error> {
+    let data_size = raw_image.header().data_size().unwrap_or(0) as usize;
+    let pixel_data = &raw_image.pixels()[..data_size];
+
+    write_pixel_data(
+        args,
+        output_file,
+        raw_image.color_space(),
+        raw_image.data_format(),
+        (raw_image.width(), raw_image.height()),
+        1,
+        |writer| {
+            let mut writer = ChecksumWriter::new(writer);
+            writer.write_all(pixel_data)?;
+            writer.finish()
+        },
+    )?;
+
+    if !args.quiet {
+        eprintln!(
+            "{:?} {:?} {} {}",
+            raw_image.color_space(),
+            raw_image.data_format(),
+            raw_image.width(),
+            raw_image.height()
+        );
+    }
+
+    Ok(())
+}
+
+// The palette file stores one RGBA8 color per index, in the output color space,
+// as a single row, and only has a header if the main output has one embedded.
+fn write_palette_output(
+    args: &Arguments,
+    palette_file: &Path,
+    color_space: ColorSpace,
+    palette: &Image,
+) -> Result<(), Error> {
+    let mut pixel_data = vec![];
+
+    store_pixels(
+        palette,
+        DataFormat::RGBA8,
+        None,
+        args.byte_order,
+        args.rounding,
+        &mut pixel_data,
+    )?;
+
+    let mut file = BufWriter::new(create_output(palette_file)?);
+
+    if args.header {
+        let header = build_header(
+            color_space,
+            DataFormat::RGBA8,
+            (palette.width, palette.height),
+            1,
+            output_flags(args, DataFormat::RGBA8)
+                & !(Header::FLAG_METADATA
+                    | Header::FLAG_ORIGIN_BOTTOM_LEFT
+                    | Header::ROW_ALIGNMENT_MASK),
+            Header::compute_checksum(&pixel_data),
+        )?;
+
+        file.write_all(header.as_bytes())?;
+    }
+
+    file.write_all(&pixel_data)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+// The SOURCE_DATE_EPOCH variable overrides the current time, which allows the
+// output to be reproduced exactly, as per the reproducible builds convention.
+fn creation_timestamp() -> Result<u64, Error> {
+    match var("SOURCE_DATE_EPOCH") {
+        Ok(timestamp) => Ok(timestamp.parse()?),
+        Err(_) => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+fn output_size(
+    args: &Arguments,
+    data_format: DataFormat,
+    width: u32,
+    height: u32,
+) -> Result<u64, Error> {
+    let mut size = metadata_trailer(args)?.len() as u64;
+
+    if args.header {
+        size += size_of::<Header>() as u64;
+    }
+
+    let (mut width, mut height) = (width, height);
+
+    loop {
+        let header = HeaderBuilder::new()
+            .data_format(data_format)
+            .dimensions(width, height)
+            .flags(output_flags(args, data_format))
+            .build();
+
+        match header
+            .data_size()
+            .and_then(|data_size| size.checked_add(data_size))
+        {
+            Some(total) => size = total,
+            None => {
+                return Err(
+                    ConvertError::InvalidImage("output size is too large".to_owned()).into(),
+                )
+            }
+        }
+
+        if !args.mips || (width == 1 && height == 1) {
+            return Ok(size);
+        }
+
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+}
+
+fn print_info(file: &Path) -> Result<(), Error> {
+    let bytes = read_source(file)?;
+
+    let (header, data) = match Header::parse_slice(&bytes) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            return Err(ConvertError::InvalidHeader(match error {
+                HeaderError::TooShort => "file is too short to contain a header",
+                HeaderError::InvalidColorSpace => "header has an invalid color space",
+                HeaderError::InvalidDataFormat => "header has an invalid data format",
+            })
+            .into())
+        }
+    };
+
+    println!(
+        "color space: {}",
+        header.color_space.parse_or(ColorSpace::NonColor)
+    );
+    println!(
+        "data format: {}",
+        header.data_format.parse_or(DataFormat::R32F)
+    );
+    println!(
+        "dimensions: {}x{}",
+        header.dimensions[0], header.dimensions[1]
+    );
+
+    if header.layer_count() > 1 {
+        println!("layers: {}", header.layer_count());
+    }
+
+    if header.flags & Header::ROW_ALIGNMENT_MASK != 0 {
+        match header.row_alignment() {
+            Some(row_alignment) => println!("row alignment: {} bytes", row_alignment),
+            None => println!("row alignment: too large"),
+        }
+    }
+
+    match header.data_size() {
+        Some(data_size) => println!("data size: {} bytes", data_size),
+        None => println!("data size: too large"),
+    }
+
+    match header.total_size() {
+        Some(total_size) => println!("total size: {} bytes", total_size),
+        None => println!("total size: too large"),
+    }
+
+    if header.tool_version != 0 {
+        println!(
+            "tool version: {}.{}.{}",
+            header.tool_version >> 16,
+            header.tool_version >> 8 & 0xff,
+            header.tool_version & 0xff
+        );
+    }
+
+    if header.timestamp != 0 {
+        println!("timestamp: {}", header.timestamp);
+    }
+
+    if header.has_metadata() {
+        match Metadata::parse(data) {
+            Ok(metadata) => {
+                for (key, value) in metadata.iter() {
+                    println!("meta: {}={}", key, value);
+                }
+            }
+            Err(_) => println!("meta: invalid"),
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_checksum(args: &Arguments, file: &Path) -> Result<(), Error> {
+    let bytes = read_source(file)?;
+
+    let image = parse_raw_image(&bytes)?;
+
+    let checksum = Header::compute_checksum(image.pixels());
+
+    if checksum != image.header().checksum {
+        return Err(ConvertError::ChecksumMismatch {
+            expected: image.header().checksum,
+            computed: checksum,
+        }
+        .into());
+    }
+
+    if !args.quiet {
+        eprintln!("checksum {:08x} OK", checksum);
+    }
+
+    Ok(())
+}
+
+fn list_formats() {
+    println!("data formats:");
+
+    for &data_format in DataFormat::all() {
+        let pixel_size = match data_format.bits_per_pixel() {
+            Some(1) => "1 bit per pixel".to_owned(),
+            Some(8) => "1 byte per pixel".to_owned(),
+            Some(bits) if bits < 8 => format!("{} bits per pixel", bits),
+            Some(bits) => format!("{} bytes per pixel", bits / 8),
+            None => "8 bytes per 4x4 block".to_owned(),
+        };
+
+        let channels = match data_format.channel_count() {
+            1 => "1 channel".to_owned(),
+            count => format!("{} channels", count),
+        };
+
+        println!(
+            "  {:<12} {}, {}, {}-byte row alignment",
+            data_format.to_string(),
+            pixel_size,
+            channels,
+            data_format.row_alignment()
+        );
+    }
+
+    println!("color spaces:");
+
+    for &color_space in ColorSpace::all() {
+        println!(
+            "  {:<12} {}",
+            color_space.to_string(),
+            color_space.description()
+        );
+    }
+}
+
+// Input
+
+fn parse_raw_image(bytes: &[u8]) -> Result<RawImage<'_>, Error> {
+    RawImage::parse(bytes).map_err(|error| {
+        ConvertError::InvalidHeader(match error {
+            RawImageError::InvalidHeader => "file does not have a valid header",
+            RawImageError::TooShort => "file is too short for its header",
+            RawImageError::InvalidMetadata => "file has an invalid metadata trailer",
+        })
+        .into()
+    })
+}
+
+fn read_source(path: &Path) -> Result<Vec<u8>, Error> {
+    if path == Path::new("-") {
+        let mut bytes = vec![];
+        stdin().lock().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else {
+        Ok(read(path)?)
+    }
+}
+
+// Untagged images are assumed to be sRGB, which is by far the most common case.
+fn detect_color_space(bytes: &[u8]) -> ColorSpace {
+    let color_space = match guess_format(bytes) {
+        Ok(ImageFormat::PNG) => detect_png_color_space(bytes),
+        _ => None,
+    };
+
+    color_space.unwrap_or_else(|| {
+        eprintln!("warning: no recognized color profile, assuming SRGB");
+        ColorSpace::SRGB
+    })
+}
+
+// The cICP chunk takes precedence over an ICC profile, which itself takes
+// precedence over the sRGB chunk, as per the PNG specification. ICC profiles
+// are compressed, so they are only recognized by their name.
+fn detect_png_color_space(bytes: &[u8]) -> Option<ColorSpace> {
+    let (mut cicp, mut iccp, mut srgb) = (None, None, None);
+
+    let mut chunks = bytes.get(8..)?;
+
+    while chunks.len() >= 12 {
+        let size = BE::read_u32(&chunks[..4]) as usize;
+        let contents = chunks[8..].get(..size)?;
+
+        match &chunks[4..8] {
+            b"cICP" if size == 4 => {
+                cicp = match (contents[0], contents[1]) {
+                    (1, 13) => Some(ColorSpace::SRGB),
+                    (1, 8) => Some(ColorSpace::LinearSRGB),
+                    _ => None,
+                }
+            }
+            b"iCCP" => {
+                let name = contents.split(|&byte| byte == 0).next()?;
+                let name = String::from_utf8_lossy(name).to_lowercase();
+
+                if name.contains("srgb") {
+                    iccp = Some(ColorSpace::SRGB);
+                } else if name.contains("adobe rgb") || name.contains("adobergb") {
+                    iccp = Some(ColorSpace::AdobeRGB);
+                }
+            }
+            b"sRGB" => srgb = Some(ColorSpace::SRGB),
+            b"IDAT" => break,
+            _ => {}
+        }
+
+        chunks = &chunks[(12 + size).min(chunks.len())..];
+    }
+
+    cicp.or(iccp).or(srgb)
+}
+
+// Grayscale images are normally expanded to RGB, but when only one channel is
+// going to be stored they are loaded directly into the red channel instead, in
+// which case true is returned along with the image. A single frame is loaded
+// from animated images, and other images only have the first frame; notably,
+// only the default image of an APNG is loaded.
+fn load_image(
+    bytes: &[u8],
+    luma_only: bool,
+    frame: usize,
+    max_pixels: u64,
+) -> Result<(Image, bool), Error> {
+    // The image crate has no AVIF decoder, nor does it recognize AVIF files.
+    if bytes.get(4..12) == Some(b"ftypavif") {
+        return Err(ConvertError::UnsupportedFileType("AVIF".to_owned()).into());
+    }
+
+    let format =
+        guess_format(bytes).map_err(|_| ConvertError::UnsupportedFileType("unknown".to_owned()))?;
+
+    if frame != 0 && !matches!(format, ImageFormat::GIF | ImageFormat::WEBP) {
+        return Err(ConvertError::UnsupportedCombination(format!(
+            "{:?}: only the first frame can be loaded",
+            format
+        ))
+        .into());
+    }
+
+    match format {
+        ImageFormat::HDR => Ok((load_hdr_image(bytes, max_pixels)?, false)),
+        ImageFormat::PNG => load_png_image(bytes, luma_only, max_pixels),
+        ImageFormat::JPEG => load_dynamic_image(bytes, luma_only, max_pixels),
+        ImageFormat::PNM => load_dynamic_image(bytes, luma_only, max_pixels),
+        ImageFormat::BMP => load_dynamic_image(bytes, luma_only, max_pixels),
+        ImageFormat::TIFF => load_tiff_image(bytes, luma_only, max_pixels),
+        ImageFormat::WEBP => load_webp_image(bytes, luma_only, frame, max_pixels),
+        ImageFormat::GIF => Ok((load_gif_image(bytes, frame, max_pixels)?, false)),
+        unsupported => Err(ConvertError::UnsupportedFileType(format!("{:?}", unsupported)).into()),
+    }
+}
+
+// The image is only checked against the pixel limit once decoded, since the
+// image crate does not expose the dimensions before decoding.
+fn load_dynamic_image(
+    bytes: &[u8],
+    luma_only: bool,
+    max_pixels: u64,
+) -> Result<(Image, bool), Error> {
+    let data = load_from_memory(bytes)?;
+
+    check_pixel_count(data.width(), data.height(), max_pixels)?;
+
+    let data = match data {
+        DynamicImage::ImageLuma8(data) if luma_only => {
+            let mut image = Image::new(data.width(), data.height())?;
+
+            for (input, pixel) in data.pixels().zip(&mut image.pixels) {
+                pixel.r = input.0[0] as f64 / 255.0;
+                pixel.a = 1.0;
+            }
+
+            return Ok((image, true));
+        }
+        DynamicImage::ImageLumaA8(data) if luma_only => {
+            let mut image = Image::new(data.width(), data.height())?;
+
+            for (input, pixel) in data.pixels().zip(&mut image.pixels) {
+                pixel.r = input.0[0] as f64 / 255.0;
+                pixel.a = input.0[1] as f64 / 255.0;
+            }
+
+            return Ok((image, true));
+        }
+        data => data.to_rgba(),
+    };
+
+    Ok((load_rgba_image(&data)?, false))
+}
+
+// The decoder composites each frame onto the previous ones, so every frame is
+// loaded as a full image, at the cost of decoding all of the frames before it.
+fn load_gif_image(bytes: &[u8], frame: usize, max_pixels: u64) -> Result<Image, Error> {
+    let mut frames = gif::Decoder::new(Cursor::new(bytes))?.into_frames();
+
+    match frames.nth(frame) {
+        Some(data) => {
+            let data = data?.into_buffer();
+            check_pixel_count(data.width(), data.height(), max_pixels)?;
+            load_rgba_image(&data)
+        }
+        None => Err(ConvertError::InvalidImage(format!("GIF image has no frame {}", frame)).into()),
+    }
+}
+
+fn load_rgba_image(data: &RgbaImage) -> Result<Image, Error> {
+    let mut image = Image::new(data.width(), data.height())?;
+
+    for (input, pixel) in data.pixels().zip(&mut image.pixels) {
+        pixel.r = input.0[0] as f64 / 255.0;
+        pixel.g = input.0[1] as f64 / 255.0;
+        pixel.b = input.0[2] as f64 / 255.0;
+        pixel.a = input.0[3] as f64 / 255.0;
+    }
+
+    Ok(image)
+}
+
+// The image crate only decodes 8-bit PNG images, so 16-bit images are decoded
+// separately to preserve their full precision.
+fn load_png_image(bytes: &[u8], luma_only: bool, max_pixels: u64) -> Result<(Image, bool), Error> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+
+    let (info, mut reader) = decoder.read_info()?;
+
+    if info.bit_depth != png::BitDepth::Sixteen {
+        return load_dynamic_image(bytes, luma_only, max_pixels);
+    }
+
+    check_pixel_count(info.width, info.height, max_pixels)?;
+
+    let mut image = Image::new(info.width, info.height)?;
+
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    for (input, row) in data
+        .chunks_exact(info.line_size)
+        .zip(image.pixels.chunks_exact_mut(info.width as usize))
+    {
+        load_png_row(&info, input, row, luma_only);
+    }
+
+    let grayscale = matches!(
+        info.color_type,
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha
+    );
+
+    Ok((image, grayscale && luma_only))
+}
+
+fn load_png_row(info: &png::OutputInfo, data: &[u8], row: &mut [Pixel], luma_only: bool) {
+    let samples = info.color_type.samples();
+
+    let sample_size = match info.bit_depth {
+        png::BitDepth::Sixteen => 2,
+        _ => 1,
+    };
+
+    for (input, pixel) in data.chunks_exact(sample_size * samples).zip(row) {
+        let sample = |i: usize| match sample_size {
+            2 => u16::from_be_bytes([input[2 * i], input[2 * i + 1]]) as f64 / 65535.0,
+            _ => input[i] as f64 / 255.0,
+        };
+
+        match info.color_type {
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha if luma_only => {
+                pixel.r = sample(0);
+            }
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                pixel.r = sample(0);
+                pixel.g = sample(0);
+                pixel.b = sample(0);
+            }
+            _ => {
+                pixel.r = sample(0);
+                pixel.g = sample(1);
+                pixel.b = sample(2);
+            }
+        }
+
+        pixel.a = match info.color_type {
+            png::ColorType::GrayscaleAlpha => sample(1),
+            png::ColorType::RGBA => sample(3),
+            _ => 1.0,
+        };
+    }
+}
+
+// Likewise for TIFF images, which are decoded separately when they have 16-bit
+// samples. Floating-point TIFF images are not supported by the TIFF decoder.
+fn load_tiff_image(bytes: &[u8], luma_only: bool, max_pixels: u64) -> Result<(Image, bool), Error> {
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes))?;
+
+    let color_type = decoder.colortype()?;
+
+    let samples = match color_type {
+        tiff::ColorType::Gray(16) => 1,
+        tiff::ColorType::GrayA(16) => 2,
+        tiff::ColorType::RGB(16) => 3,
+        tiff::ColorType::RGBA(16) => 4,
+        _ => return load_dynamic_image(bytes, luma_only, max_pixels),
+    };
+
+    let (width, height) = decoder.dimensions()?;
+
+    check_pixel_count(width, height, max_pixels)?;
+
+    let mut image = Image::new(width, height)?;
+
+    let data = match decoder.read_image()? {
+        tiff::decoder::DecodingResult::U16(data) => data,
+        tiff::decoder::DecodingResult::U8(_) => {
+            return Err(ConvertError::InvalidImage("unexpected 8-bit TIFF data".to_owned()).into())
+        }
+    };
+
+    for (input, pixel) in data.chunks_exact(samples).zip(&mut image.pixels) {
+        let sample = |i: usize| input[i] as f64 / 65535.0;
+
+        if samples >= 3 {
+            pixel.r = sample(0);
+            pixel.g = sample(1);
+            pixel.b = sample(2);
+        } else if luma_only {
+            pixel.r = sample(0);
+        } else {
+            pixel.r = sample(0);
+            pixel.g = sample(0);
+            pixel.b = sample(0);
+        }
+
+        pixel.a = match samples {
+            2 => sample(1),
+            4 => sample(3),
+            _ => 1.0,
+        };
+    }
+
+    Ok((image, samples < 3 && luma_only))
+}
+
+// The WebP decoder of the image crate only supports simple lossy images, so the
+// requested lossy frame of extended images, including animated ones, is wrapped
+// into a simple image before being decoded. It only decodes the luma plane, so
+// the image can only be stored in a single channel, which is normally rejected
+// before the image is read.
+fn load_webp_image(
+    bytes: &[u8],
+    luma_only: bool,
+    frame: usize,
+    max_pixels: u64,
+) -> Result<(Image, bool), Error> {
+    if !luma_only {
+        return Err(ConvertError::UnsupportedCombination(
+            "WebP images can only be stored in single-channel formats".to_owned(),
+        )
+        .into());
+    }
+
+    let frame = match find_webp_frame(&bytes[12.min(bytes.len())..], frame)? {
+        Some(frame) => frame,
+        None if frame == 0 => {
+            return Err(
+                ConvertError::InvalidImage("WebP image has no lossy frame".to_owned()).into(),
+            )
+        }
+        None => {
+            return Err(
+                ConvertError::InvalidImage(format!("WebP image has no frame {}", frame)).into(),
+            )
+        }
+    };
+
+    let padding = frame.len() % 2;
+
+    let mut simple = Vec::with_capacity(20 + frame.len() + padding);
+    simple.extend_from_slice(b"RIFF");
+    simple.write_u32::<LE>((12 + frame.len() + padding) as u32)?;
+    simple.extend_from_slice(b"WEBPVP8 ");
+    simple.write_u32::<LE>(frame.len() as u32)?;
+    simple.extend_from_slice(frame);
+    simple.resize(simple.len() + padding, 0);
+
+    load_dynamic_image(&simple, luma_only, max_pixels)
+}
+
+// Returns the contents of the VP8 chunk of the given frame, looking inside the
+// animation frames. Frames are not composited onto the previous ones, so frames
+// after the first must cover the whole canvas.
+fn find_webp_frame(mut chunks: &[u8], mut frame: usize) -> Result<Option<&[u8]>, Error> {
+    let selected = frame;
+    let mut canvas = None;
+
+    while chunks.len() >= 8 {
+        let size = LE::read_u32(&chunks[4..8]) as usize;
+
+        let contents = match chunks[8..].get(..size) {
+            Some(contents) => contents,
+            None => {
+                return Err(ConvertError::InvalidImage("WebP chunk is truncated".to_owned()).into())
+            }
+        };
+
+        match &chunks[..4] {
+            b"VP8 " if frame == 0 => return Ok(Some(contents)),
+            b"VP8L" => {
+                return Err(ConvertError::UnsupportedFileType("lossless WebP".to_owned()).into())
+            }
+            b"VP8X" if size >= 10 => {
+                canvas = Some((
+                    LE::read_u24(&contents[4..7]) + 1,
+                    LE::read_u24(&contents[7..10]) + 1,
+                ))
+            }
+            b"ANMF" if size >= 16 && frame == 0 => {
+                let offset = (LE::read_u24(&contents[0..3]), LE::read_u24(&contents[3..6]));
+                let dimensions = (
+                    LE::read_u24(&contents[6..9]) + 1,
+                    LE::read_u24(&contents[9..12]) + 1,
+                );
+
+                if selected != 0 && (offset != (0, 0) || Some(dimensions) != canvas) {
+                    return Err(ConvertError::UnsupportedCombination(format!(
+                        "WebP frame {} does not cover the whole canvas",
+                        selected
+                    ))
+                    .into());
+                }
+
+                return find_webp_frame(&contents[16..], 0);
+            }
+            b"ANMF" => frame -= 1,
+            _ => {}
+        }
+
+        chunks = &chunks[(8 + size + size % 2).min(chunks.len())..];
+    }
+
+    Ok(None)
+}
+
+// Only the base level of a mipmapped file is loaded, and rows stored from the
+// bottom up are flipped back so that the image is processed top-down as usual.
+fn load_raw_image(raw_image: &RawImage, max_pixels: u64) -> Result<Image, Error> {
+    check_transcodable(raw_image.header())?;
+    check_pixel_count(raw_image.width(), raw_image.height(), max_pixels)?;
+
+    let mut image = Image::new(raw_image.width(), raw_image.height())?;
+
+    let pixels = decode_pixels(
+        raw_image.data_format(),
+        raw_image.width(),
+        raw_image.height(),
+        raw_image.pixels(),
+    )?;
+
+    for (input, pixel) in pixels.iter().zip(&mut image.pixels) {
+        *pixel = Pixel::from_array(input.map(f64::from));
+    }
+
+    if raw_image.header().is_origin_bottom_left() {
+        image.flip_vertically();
+    }
+
+    Ok(image)
+}
+
+fn check_transcodable(header: &Header) -> Result<(), Error> {
+    if header.byte_order() == Endianness::Big {
+        return Err(ConvertError::UnsupportedCombination(
+            "cannot transcode big-endian pixel data".to_owned(),
+        )
+        .into());
+    }
+
+    if header.is_premultiplied() {
+        return Err(ConvertError::UnsupportedCombination(
+            "cannot transcode premultiplied pixel data".to_owned(),
+        )
+        .into());
+    }
+
+    if header.flags & Header::ROW_ALIGNMENT_MASK != 0 {
+        return Err(ConvertError::UnsupportedCombination(
+            "cannot transcode pixel data with a row alignment override".to_owned(),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn load_hdr_image(bytes: &[u8], max_pixels: u64) -> Result<Image, Error> {
+    let loaded = hdr::HDRDecoder::new(bytes)?;
+
+    let metadata = loaded.metadata();
+
+    check_pixel_count(metadata.width, metadata.height, max_pixels)?;
+
+    let mut image = Image::new(metadata.width, metadata.height)?;
+
+    let data = loaded.read_image_hdr()?;
+
+    for (input, pixel) in data.iter().zip(&mut image.pixels) {
+        pixel.r = input.0[0] as f64;
+        pixel.g = input.0[1] as f64;
+        pixel.b = input.0[2] as f64;
+        pixel.a = 1.0;
+    }
+
+    Ok(image)
+}
+
+// Only PNG images and raw images being transcoded can be decoded a few rows at
+// a time. Rows of raw images are decoded directly from the pixel data, in the
+// order they are stored in, and each strip is flipped if stored bottom-up.
+enum StripSource<'a> {
+    Png {
+        reader: Box<png::Reader<&'a [u8]>>,
+        info: png::OutputInfo,
+    },
+    Raw(&'a RawImage<'a>),
+}
+
+impl<'a> StripSource<'a> {
+    fn new(bytes: &'a [u8], raw_image: Option<&'a RawImage<'a>>) -> Result<Self, Error> {
+        if let Some(raw_image) = raw_image {
+            check_transcodable(raw_image.header())?;
+
+            if raw_image.data_format() == DataFormat::BC1 {
+                return Err(ConvertError::UnsupportedCombination(
+                    "BC1: cannot be converted in strips".to_owned(),
+                )
+                .into());
+            }
+
+            return Ok(StripSource::Raw(raw_image));
+        }
+
+        match guess_format(bytes)
+            .map_err(|_| ConvertError::UnsupportedFileType("unknown".to_owned()))?
+        {
+            ImageFormat::PNG => {}
+            unsupported => {
+                return Err(ConvertError::UnsupportedCombination(format!(
+                    "{:?}: cannot be converted in strips",
+                    unsupported
+                ))
+                .into())
+            }
+        }
+
+        // 16-bit images are decoded as-is, like in load_png_image, and all other
+        // images are expanded to 8 bits per sample as the image crate does.
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+
+        let (info, reader) = decoder.read_info()?;
+
+        let (info, reader) = if info.bit_depth == png::BitDepth::Sixteen {
+            (info, reader)
+        } else {
+            let mut decoder = png::Decoder::new(bytes);
+            decoder.set_transformations(png::Transformations::EXPAND);
+            decoder.read_info()?
+        };
+
+        if reader.info().interlaced {
+            return Err(ConvertError::UnsupportedCombination(
+                "interlaced PNG: cannot be converted in strips".to_owned(),
+            )
+            .into());
+        }
+
+        Ok(StripSource::Png {
+            reader: Box::new(reader),
+            info,
+        })
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            StripSource::Png { info, .. } => (info.width, info.height),
+            StripSource::Raw(raw_image) => (raw_image.width(), raw_image.height()),
+        }
+    }
+
+    // Grayscale PNG images are always expanded to RGB, whereas single-channel raw
+    // images are decoded into the red channel only.
+    fn is_grayscale(&self) -> bool {
+        match self {
+            StripSource::Png { .. } => false,
+            StripSource::Raw(raw_image) => raw_image.data_format().channel_count() == 1,
+        }
+    }
+
+    // Strips are read in order from the top of the image, starting at row y.
+    fn read_strip(&mut self, y: u32, strip: &mut Image) -> Result<(), Error> {
+        match self {
+            StripSource::Png { reader, info } => {
+                for row in strip.pixels.chunks_exact_mut(strip.width as usize) {
+                    match reader.next_row()? {
+                        Some(data) => load_png_row(info, data, row, false),
+                        None => {
+                            return Err(ConvertError::InvalidImage(
+                                "PNG image is truncated".to_owned(),
+                            )
+                            .into())
+                        }
+                    }
+                }
+            }
+            StripSource::Raw(raw_image) => {
+                let header = raw_image.header();
+
+                let row_size = HeaderBuilder::new()
+                    .data_format(raw_image.data_format())
+                    .dimensions(raw_image.width(), 1)
+                    .build()
+                    .data_size()
+                    .expect("row size fits in the parsed image")
+                    as usize;
+
+                let first_row = if header.is_origin_bottom_left() {
+                    raw_image.height() - y - strip.height
+                } else {
+                    y
+                };
+
+                let start = first_row as usize * row_size;
+                let end = start + strip.height as usize * row_size;
+
+                let pixels = decode_pixels(
+                    raw_image.data_format(),
+                    strip.width,
+                    strip.height,
+                    &raw_image.pixels()[start..end],
+                )?;
+
+                for (input, pixel) in pixels.iter().zip(&mut strip.pixels) {
+                    *pixel = Pixel::from_array(input.map(f64::from));
+                }
+
+                if header.is_origin_bottom_left() {
+                    strip.flip_vertically();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Processing
+
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Pixel>,
+}
+
+// Huge or corrupt images are rejected cleanly before being allocated, rather
+// than exhausting memory, and a limit of zero disables the check.
+fn check_pixel_count(width: u32, height: u32, max_pixels: u64) -> Result<(), Error> {
+    if max_pixels != 0 && width as u64 * height as u64 > max_pixels {
+        return Err(ConvertError::InvalidImage(format!(
+            "image dimensions {}x{} exceed the limit of {} pixels",
+            width, height, max_pixels
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Result<Self, Error> {
+        let pixel_count = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|count| count.checked_mul(size_of::<Pixel>()).is_some());
+
+        if let Some(pixel_count) = pixel_count {
+            Ok(Self {
+                width,
+                height,
+                pixels: vec![Pixel::default(); pixel_count],
+            })
+        } else {
+            Err(ConvertError::InvalidImage(format!(
+                "image dimensions {}x{} are too large",
+                width, height
+            ))
+            .into())
+        }
+    }
+
+    pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Result<Self, Error> {
+        let filter = match filter {
+            ResizeFilter::Box => return self.resize_box(width, height),
+            ResizeFilter::Filter(filter) => filter,
+        };
+
+        let mut buffer = ImageBuffer::<Rgba<f32>, _>::new(self.width, self.height);
+
+        for (output, pixel) in buffer.pixels_mut().zip(&self.pixels) {
+            output.0 = pixel.to_array().map(|x| x as f32);
+        }
+
+        let mut image = Self::new(width, height)?;
+
+        let resized = imageops::resize(&buffer, width, height, filter);
+
+        for (input, pixel) in resized.pixels().zip(&mut image.pixels) {
+            *pixel = Pixel::from_array(input.0.map(f64::from));
+        }
+
+        Ok(image)
+    }
+
+    // Each output pixel averages the source pixels that it covers, weighted by
+    // how much of each source pixel it covers.
+    fn resize_box(&self, width: u32, height: u32) -> Result<Self, Error> {
+        let mut image = Self::new(width, height)?;
+
+        let weights_x = box_weights(self.width, width);
+        let weights_y = box_weights(self.height, height);
+
+        image
+            .pixels
+            .par_chunks_mut(width as usize)
+            .zip(&weights_y)
+            .for_each(|(row, weights_y)| {
+                for (pixel, weights_x) in row.iter_mut().zip(&weights_x) {
+                    for &(y, weight_y) in weights_y {
+                        for &(x, weight_x) in weights_x {
+                            let sample = self.pixels[(y * self.width + x) as usize];
+                            let weight = weight_x * weight_y;
+
+                            pixel.r += sample.r * weight;
+                            pixel.g += sample.g * weight;
+                            pixel.b += sample.b * weight;
+                            pixel.a += sample.a * weight;
+                        }
+                    }
+                }
+            });
+
+        Ok(image)
+    }
+
+    // Each output pixel averages a grid of samples x samples bilinear samples
+    // evenly spread over the area of the source image that it covers.
+    pub fn supersample(&self, width: u32, height: u32, samples: u32) -> Result<Self, Error> {
+        let mut image = Self::new(width, height)?;
+
+        let scale_x = self.width as f64 / width as f64;
+        let scale_y = self.height as f64 / height as f64;
+        let weight = 1.0 / (samples as f64 * samples as f64);
+
+        image
+            .pixels
+            .par_chunks_mut(width as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    for j in 0..samples {
+                        for i in 0..samples {
+                            let u = (x as f64 + (i as f64 + 0.5) / samples as f64) * scale_x;
+                            let v = (y as f64 + (j as f64 + 0.5) / samples as f64) * scale_y;
+
+                            let sample = self.sample_bilinear(u, v);
+
+                            pixel.r += sample.r * weight;
+                            pixel.g += sample.g * weight;
+                            pixel.b += sample.b * weight;
+                            pixel.a += sample.a * weight;
+                        }
+                    }
+                }
+            });
+
+        Ok(image)
+    }
+
+    // Samples the image at a position in pixels, with pixel centers at half
+    // integer coordinates and edge pixels extending past the image bounds.
+    fn sample_bilinear(&self, u: f64, v: f64) -> Pixel {
+        let x = (u - 0.5).clamp(0.0, (self.width - 1) as f64);
+        let y = (v - 0.5).clamp(0.0, (self.height - 1) as f64);
+
+        let x0 = x.floor() as u32;
+        let y0 = y.floor() as u32;
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+        let samples = [
+            (self[(x0, y0)], (1.0 - fx) * (1.0 - fy)),
+            (self[(x1, y0)], fx * (1.0 - fy)),
+            (self[(x0, y1)], (1.0 - fx) * fy),
+            (self[(x1, y1)], fx * fy),
+        ];
+
+        let mut pixel = Pixel::default();
+
+        for (sample, weight) in &samples {
+            pixel.r += sample.r * weight;
+            pixel.g += sample.g * weight;
+            pixel.b += sample.b * weight;
+            pixel.a += sample.a * weight;
+        }
+
+        pixel
+    }
+
+    pub fn downsample(&self) -> Option<Self> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+
+        let mut image = Self::new(width, height).expect("mip level is smaller than its parent");
+
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let x0 = (2 * x).min(self.width - 1);
+                let y0 = (2 * y).min(self.height - 1);
+                let x1 = (2 * x + 1).min(self.width - 1);
+                let y1 = (2 * y + 1).min(self.height - 1);
+
+                let samples = [
+                    self[(x0, y0)],
+                    self[(x1, y0)],
+                    self[(x0, y1)],
+                    self[(x1, y1)],
+                ];
+
+                let pixel = &mut image.pixels[(y * image.width + x) as usize];
+
+                for sample in &samples {
+                    pixel.r += sample.r / 4.0;
+                    pixel.g += sample.g / 4.0;
+                    pixel.b += sample.b / 4.0;
+                    pixel.a += sample.a / 4.0;
+                }
+            }
+        }
+
+        Some(image)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<&Pixel> {
+        if x < self.width && y < self.height {
+            self.pixels
+                .get(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[Pixel]> {
+        self.pixels.chunks_exact(self.width.max(1) as usize)
+    }
+
+    pub fn split_alpha(&mut self) -> Self {
+        let mut alpha = Self::new(self.width, self.height).expect("alpha has the same size");
+
+        for (pixel, output) in self.pixels.iter_mut().zip(&mut alpha.pixels) {
+            output.r = pixel.a;
+            output.a = 1.0;
+            pixel.a = 1.0;
+        }
+
+        alpha
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+
+        for y in 0..height / 2 {
+            let (upper, lower) = self.pixels.split_at_mut((height - 1 - y) * width);
+            upper[y * width..(y + 1) * width].swap_with_slice(&mut lower[..width]);
+        }
+    }
+}
+
+impl Index<(u32, u32)> for Image {
+    type Output = Pixel;
+
+    fn index(&self, (x, y): (u32, u32)) -> &Pixel {
+        match self.get(x, y) {
+            Some(pixel) => pixel,
+            None => panic!("pixel ({}, {}) is outside of the image", x, y),
+        }
+    }
+}
+
+// The image crate has no box filter, so it is implemented separately.
+#[derive(Clone, Copy, Debug)]
+pub enum ResizeFilter {
+    Box,
+    Filter(FilterType),
+}
+
+// Returns the source pixels covered by each output pixel along one axis, along
+// with the fraction of the output pixel that each of them covers.
+fn box_weights(source_size: u32, output_size: u32) -> Vec<Vec<(u32, f64)>> {
+    let scale = source_size as f64 / output_size as f64;
+
+    (0..output_size)
+        .map(|x| {
+            let start = x as f64 * scale;
+            let end = (x + 1) as f64 * scale;
+
+            (start.floor() as u32..(end.ceil() as u32).min(source_size))
+                .map(|i| {
+                    let covered = end.min(i as f64 + 1.0) - start.max(i as f64);
+                    (i, covered / scale)
+                })
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Pixel {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+// Computes the checksum of all bytes written through it.
+pub struct ChecksumWriter<W> {
+    writer: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> Result<u32, Error> {
+        self.writer.flush()?;
+        Ok(self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(bytes)?;
+        self.hasher.update(&bytes[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ToneMapping {
+    Reinhard,
+    ACES,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum HeaderFormat {
+    Binary,
+    JSON,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SourceColorSpace {
+    Auto,
+    Known(ColorSpace),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlphaPolicy {
+    Opaque,
+    Zero,
+    Keep,
+}
+
+// Alpha is coverage, and so is linear regardless of the color space, unless it
+// is known to be encoded with the same transfer function as the color channels.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlphaTransfer {
+    Linear,
+    Color,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rounding {
+    Truncate,
+    Round,
+    RoundEven,
+}
+
+impl Rounding {
+    pub fn apply(self, x: f64) -> f64 {
+        match self {
+            Self::Truncate => x.trunc(),
+            Self::Round => x.round(),
+            Self::RoundEven => x.round_ties_even(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A,
+    Zero,
+    One,
+}
+
+#[derive(Clone, Debug)]
+pub struct Swizzle {
+    channels: Vec<Channel>,
+}
+
+// Channels are indexed in RGBA order.
+impl Index<usize> for Pixel {
+    type Output = f64;
+
+    fn index(&self, channel: usize) -> &f64 {
+        match channel {
+            0 => &self.r,
+            1 => &self.g,
+            2 => &self.b,
+            3 => &self.a,
+            _ => panic!("channel {} is out of range", channel),
+        }
+    }
+}
+
+impl IndexMut<usize> for Pixel {
+    fn index_mut(&mut self, channel: usize) -> &mut f64 {
+        match channel {
+            0 => &mut self.r,
+            1 => &mut self.g,
+            2 => &mut self.b,
+            3 => &mut self.a,
+            _ => panic!("channel {} is out of range", channel),
+        }
+    }
+}
+
+type Matrix = [[f64; 3]; 3];
+
+const LINEAR_SRGB_TO_CIE_XYZ: Matrix = [
+    [0.4124, 0.3576, 0.1805],
+    [0.2126, 0.7152, 0.0722],
+    [0.0193, 0.1192, 0.9505],
+];
+
+const CIE_XYZ_TO_LINEAR_SRGB: Matrix = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+
+const D65_WHITE_POINT: [f64; 3] = [0.950_47, 1.0, 1.088_83];
+
+const ADOBE_RGB_TO_CIE_XYZ: Matrix = [
+    [0.576_730_9, 0.185_554_0, 0.188_185_2],
+    [0.297_376_9, 0.627_349_1, 0.075_274_1],
+    [0.027_034_3, 0.070_687_2, 0.991_108_5],
+];
+
+const CIE_XYZ_TO_ADOBE_RGB: Matrix = [
+    [2.041_369_0, -0.564_946_4, -0.344_694_4],
+    [-0.969_266_0, 1.876_010_8, 0.041_556_0],
+    [0.013_447_4, -0.118_389_7, 1.015_409_6],
+];
+
+impl Pixel {
+    pub fn from_array([r, g, b, a]: [f64; 4]) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub fn to_array(self) -> [f64; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn convert_into_cie_xyz(self, color_space: ColorSpace, gamma: Option<f64>) -> Self {
+        match color_space {
+            ColorSpace::NonColor | ColorSpace::CIEXYZ => self,
+            ColorSpace::LinearSRGB | ColorSpace::SRGB => self
+                .decode_transfer(color_space, gamma)
+                .transform(&LINEAR_SRGB_TO_CIE_XYZ),
+            ColorSpace::AdobeRGB => self
+                .decode_transfer(color_space, gamma)
+                .transform(&ADOBE_RGB_TO_CIE_XYZ),
+            ColorSpace::HSV => self
+                .convert_into_linear_srgb_from_hsv()
+                .transform(&LINEAR_SRGB_TO_CIE_XYZ),
+            ColorSpace::YCbCr709 => self
+                .convert_into_srgb_from_ycbcr709()
+                .decode_transfer(ColorSpace::SRGB, gamma)
+                .transform(&LINEAR_SRGB_TO_CIE_XYZ),
+            ColorSpace::CIELAB => self.convert_into_cie_xyz_from_cie_lab(),
+            ColorSpace::CIExyY => self.convert_into_cie_xyz_from_cie_xyy(),
+        }
+    }
+
+    pub fn convert_from_cie_xyz(self, color_space: ColorSpace, gamma: Option<f64>) -> Self {
+        match color_space {
+            ColorSpace::NonColor | ColorSpace::CIEXYZ => self,
+            ColorSpace::LinearSRGB | ColorSpace::SRGB => self
+                .transform(&CIE_XYZ_TO_LINEAR_SRGB)
+                .encode_transfer(color_space, gamma),
+            ColorSpace::AdobeRGB => self
+                .transform(&CIE_XYZ_TO_ADOBE_RGB)
+                .encode_transfer(color_space, gamma),
+            ColorSpace::HSV => unreachable!("HSV is not a valid output color space"),
+            ColorSpace::YCbCr709 => self
+                .transform(&CIE_XYZ_TO_LINEAR_SRGB)
+                .encode_transfer(ColorSpace::SRGB, gamma)
+                .convert_from_srgb_into_ycbcr709(),
+            ColorSpace::CIELAB => self.convert_from_cie_xyz_into_cie_lab(),
+            ColorSpace::CIExyY => self.convert_from_cie_xyz_into_cie_xyy(),
+        }
+    }
+
+    // Premultiplication is always done in linear light, so gamma-encoded color
+    // data is temporarily decoded before being multiplied by the alpha value.
+    pub fn premultiply(self, color_space: ColorSpace, gamma: Option<f64>) -> Self {
+        let mut pixel = self.decode_transfer(color_space, gamma);
+
+        pixel.r *= pixel.a;
+        pixel.g *= pixel.a;
+        pixel.b *= pixel.a;
+
+        pixel.encode_transfer(color_space, gamma)
+    }
+
+    // Fully transparent pixels have no color left to recover and are unchanged.
+    pub fn unpremultiply(self, color_space: ColorSpace, gamma: Option<f64>) -> Self {
+        if self.a <= 0.0 {
+            return self;
+        }
+
+        let mut pixel = self.decode_transfer(color_space, gamma);
+
+        pixel.r /= pixel.a;
+        pixel.g /= pixel.a;
+        pixel.b /= pixel.a;
+
+        pixel.encode_transfer(color_space, gamma)
+    }
+
+    // The luminance is computed in linear light and stored in every color channel.
+    pub fn luma(self, color_space: ColorSpace, gamma: Option<f64>, weights: [f64; 3]) -> Self {
+        let pixel = self.decode_transfer(color_space, gamma);
+
+        let luma = pixel.r * weights[0] + pixel.g * weights[1] + pixel.b * weights[2];
+
+        Self {
+            r: luma,
+            g: luma,
+            b: luma,
+            a: pixel.a,
+        }
+        .encode_transfer(color_space, gamma)
+    }
+
+    // Exposure and tone mapping are done in linear light, so gamma-encoded color
+    // data is temporarily decoded in the same way as for premultiplication.
+    pub fn tone_map(
+        self,
+        color_space: ColorSpace,
+        gamma: Option<f64>,
+        exposure: f64,
+        tone_mapping: Option<ToneMapping>,
+    ) -> Self {
+        let mut pixel = self.decode_transfer(color_space, gamma);
+
+        let scale = exposure.exp2();
+
+        let operator: fn(f64) -> f64 = match tone_mapping {
+            Some(ToneMapping::Reinhard) => |x| {
+                let x = x.max(0.0);
+                x / (1.0 + x)
+            },
+            Some(ToneMapping::ACES) => |x| {
+                let y = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                y.clamp(0.0, 1.0)
+            },
+            None => |x| x,
+        };
+
+        pixel.r = operator(pixel.r * scale);
+        pixel.g = operator(pixel.g * scale);
+        pixel.b = operator(pixel.b * scale);
+
+        pixel.encode_transfer(color_space, gamma)
+    }
+
+    // Normals are decoded from [0, 1] and normalized, and only their X and Y are
+    // kept, remapped to [0, 1] again unless they are to be stored as signed data.
+    pub fn normal_xy(self, signed: bool) -> Self {
+        let x = 2.0 * self.r - 1.0;
+        let y = 2.0 * self.g - 1.0;
+        let z = 2.0 * self.b - 1.0;
+
+        let length = (x * x + y * y + z * z).sqrt();
+
+        let (x, y) = if length > 0.0 {
+            (x / length, y / length)
+        } else {
+            (0.0, 0.0)
+        };
+
+        if signed {
+            Self {
+                r: x,
+                g: y,
+                b: 0.0,
+                a: self.a,
+            }
+        } else {
+            Self {
+                r: x * 0.5 + 0.5,
+                g: y * 0.5 + 0.5,
+                b: 0.0,
+                a: self.a,
+            }
+        }
+    }
+
+    pub fn clamp(self, min: f64, max: f64) -> Self {
+        Self {
+            r: self.r.clamp(min, max),
+            g: self.g.clamp(min, max),
+            b: self.b.clamp(min, max),
+            a: self.a.clamp(min, max),
+        }
+    }
+
+    pub fn sanitize(self, max: f64) -> Self {
+        let sanitize = |x: f64| if x.is_nan() { 0.0 } else { x.clamp(-max, max) };
+
+        Self {
+            r: sanitize(self.r),
+            g: sanitize(self.g),
+            b: sanitize(self.b),
+            a: sanitize(self.a),
+        }
+    }
+
+    // Output channels not covered by the swizzle are left unchanged.
+    pub fn swizzle(self, swizzle: &Swizzle) -> Self {
+        let mut channels = self.to_array();
+
+        for (output, channel) in channels.iter_mut().zip(&swizzle.channels) {
+            *output = match channel {
+                Channel::R => self[0],
+                Channel::G => self[1],
+                Channel::B => self[2],
+                Channel::A => self[3],
+                Channel::Zero => 0.0,
+                Channel::One => 1.0,
+            };
+        }
+
+        Self::from_array(channels)
+    }
+
+    fn transform(self, matrix: &Matrix) -> Self {
+        let mut pixel = self;
+
+        pixel.r = matrix[0][0] * self.r + matrix[0][1] * self.g + matrix[0][2] * self.b;
+        pixel.g = matrix[1][0] * self.r + matrix[1][1] * self.g + matrix[1][2] * self.b;
+        pixel.b = matrix[2][0] * self.r + matrix[2][1] * self.g + matrix[2][2] * self.b;
+
+        pixel
+    }
+
+    // A gamma override replaces the piecewise sRGB transfer function with a pure
+    // power law, which is what some legacy content was authored with.
+    fn decode_transfer(self, color_space: ColorSpace, gamma: Option<f64>) -> Self {
+        match (color_space, gamma) {
+            (ColorSpace::SRGB, Some(gamma)) => self.apply_transfer(|x| Self::power_law(x, gamma)),
+            (ColorSpace::SRGB, None) => self.apply_transfer(Self::convert_from_gamma_srgb),
+            (ColorSpace::AdobeRGB, _) => self.apply_transfer(Self::convert_from_gamma_adobe_rgb),
+            _ => self,
+        }
+    }
+
+    fn encode_transfer(self, color_space: ColorSpace, gamma: Option<f64>) -> Self {
+        match (color_space, gamma) {
+            (ColorSpace::SRGB, Some(gamma)) => {
+                self.apply_transfer(|x| Self::power_law(x, 1.0 / gamma))
+            }
+            (ColorSpace::SRGB, None) => self.apply_transfer(Self::convert_into_gamma_srgb),
+            (ColorSpace::AdobeRGB, _) => self.apply_transfer(Self::convert_into_gamma_adobe_rgb),
+            _ => self,
+        }
+    }
+
+    // The transfer functions never apply to alpha, which is handled separately
+    // here in the rare case where it is encoded in the same way as color.
+    fn convert_alpha_transfer(
+        self,
+        source_color_space: ColorSpace,
+        output_color_space: ColorSpace,
+        gamma: Option<f64>,
+    ) -> Self {
+        let alpha = Self {
+            r: self.a,
+            ..Self::default()
+        };
+
+        Self {
+            a: alpha
+                .decode_transfer(source_color_space, gamma)
+                .encode_transfer(output_color_space, gamma)
+                .r,
+            ..self
+        }
+    }
+
+    fn apply_transfer(self, transfer: impl Fn(f64) -> f64) -> Self {
+        let mut pixel = self;
+
+        pixel.r = transfer(self.r);
+        pixel.g = transfer(self.g);
+        pixel.b = transfer(self.b);
+
+        pixel
+    }
+
+    fn convert_into_linear_srgb_from_hsv(self) -> Self {
+        let mut pixel = self;
+
+        let h = (self.r - self.r.floor()) * 6.0;
+        let c = self.b * self.g;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let m = self.b - c;
+
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        pixel.r = r + m;
+        pixel.g = g + m;
+        pixel.b = b + m;
+
+        pixel
+    }
+
+    fn convert_into_srgb_from_ycbcr709(self) -> Self {
+        let mut pixel = self;
+
+        let (y, cb, cr) = (self.r, self.g - 0.5, self.b - 0.5);
+
+        pixel.r = y + 1.5748 * cr;
+        pixel.g = y - 0.1873 * cb - 0.4681 * cr;
+        pixel.b = y + 1.8556 * cb;
+
+        pixel
+    }
+
+    fn convert_from_srgb_into_ycbcr709(self) -> Self {
+        let mut pixel = self;
+
+        let y = 0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b;
+
+        pixel.r = y;
+        pixel.g = (self.b - y) / 1.8556 + 0.5;
+        pixel.b = (self.r - y) / 1.5748 + 0.5;
+
+        pixel
+    }
+
+    fn convert_into_cie_xyz_from_cie_lab(self) -> Self {
+        let mut pixel = self;
+
+        let fy = (self.r + 16.0) / 116.0;
+        let fx = fy + self.g / 500.0;
+        let fz = fy - self.b / 200.0;
+
+        pixel.r = D65_WHITE_POINT[0] * Self::cie_lab_inverse_transfer(fx);
+        pixel.g = D65_WHITE_POINT[1] * Self::cie_lab_inverse_transfer(fy);
+        pixel.b = D65_WHITE_POINT[2] * Self::cie_lab_inverse_transfer(fz);
+
+        pixel
+    }
+
+    fn convert_from_cie_xyz_into_cie_lab(self) -> Self {
+        let mut pixel = self;
+
+        let fx = Self::cie_lab_transfer(self.r / D65_WHITE_POINT[0]);
+        let fy = Self::cie_lab_transfer(self.g / D65_WHITE_POINT[1]);
+        let fz = Self::cie_lab_transfer(self.b / D65_WHITE_POINT[2]);
+
+        pixel.r = 116.0 * fy - 16.0;
+        pixel.g = 500.0 * (fx - fy);
+        pixel.b = 200.0 * (fy - fz);
+
+        pixel
+    }
+
+    fn convert_into_cie_xyz_from_cie_xyy(self) -> Self {
+        let mut pixel = self;
+
+        if self.g == 0.0 {
+            pixel.r = 0.0;
+            pixel.g = 0.0;
+            pixel.b = 0.0;
+        } else {
+            pixel.r = self.r * self.b / self.g;
+            pixel.g = self.b;
+            pixel.b = (1.0 - self.r - self.g) * self.b / self.g;
+        }
+
+        pixel
+    }
+
+    // The chromaticity of black is undefined, so it is taken to be the one of
+    // the white point instead, which keeps the luminance at zero.
+    fn convert_from_cie_xyz_into_cie_xyy(self) -> Self {
+        let mut pixel = self;
+
+        let (x, y, z) = if self.r + self.g + self.b == 0.0 {
+            let [x, y, z] = D65_WHITE_POINT;
+            (x, y, z)
+        } else {
+            (self.r, self.g, self.b)
+        };
+
+        pixel.r = x / (x + y + z);
+        pixel.g = y / (x + y + z);
+        pixel.b = self.g;
+
+        pixel
+    }
+
+    fn cie_lab_transfer(t: f64) -> f64 {
+        const DELTA: f64 = 6.0 / 29.0;
+
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    }
+
+    fn cie_lab_inverse_transfer(t: f64) -> f64 {
+        const DELTA: f64 = 6.0 / 29.0;
+
+        if t > DELTA {
+            t * t * t
+        } else {
+            3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+        }
+    }
+
+    fn convert_into_gamma_srgb(x: f64) -> f64 {
+        if x <= 0.003_130_8 {
+            12.92 * x
+        } else {
+            1.055 * x.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    // Values loaded from 8-bit images are looked up in a precomputed table, which
+    // is much faster than evaluating the power function for every pixel.
+    fn convert_from_gamma_srgb(x: f64) -> f64 {
+        static TABLE: OnceLock<[f64; 256]> = OnceLock::new();
+
+        let index = (x * 255.0).round();
+
+        if (0.0..=255.0).contains(&index) && x == index / 255.0 {
+            let table = TABLE.get_or_init(|| {
+                let mut table = [0.0; 256];
+
+                for (index, value) in table.iter_mut().enumerate() {
+                    *value = Self::evaluate_gamma_srgb_inverse(index as f64 / 255.0);
+                }
+
+                table
+            });
+
+            return table[index as usize];
+        }
+
+        Self::evaluate_gamma_srgb_inverse(x)
+    }
+
+    fn evaluate_gamma_srgb_inverse(x: f64) -> f64 {
+        if x <= 0.040_45 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    fn power_law(x: f64, exponent: f64) -> f64 {
+        x.signum() * x.abs().powf(exponent)
+    }
+
+    fn convert_into_gamma_adobe_rgb(x: f64) -> f64 {
+        x.signum() * x.abs().powf(256.0 / 563.0)
+    }
+
+    fn convert_from_gamma_adobe_rgb(x: f64) -> f64 {
+        x.signum() * x.abs().powf(563.0 / 256.0)
+    }
+}
+
+// Resizing is done in linear light just like supersampling.
+fn resize_image(
+    image: &Image,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+    color_space: ColorSpace,
+    gamma: Option<f64>,
+) -> Result<Image, Error> {
+    if color_space.is_linear() {
+        return image.resize(width, height, filter);
+    }
+
+    let mut linear = image.clone();
+
+    linear.pixels.par_iter_mut().for_each(|pixel| {
+        *pixel = pixel.convert_into_cie_xyz(color_space, gamma);
+    });
+
+    let mut image = linear.resize(width, height, filter)?;
+
+    image.pixels.par_iter_mut().for_each(|pixel| {
+        *pixel = pixel.convert_from_cie_xyz(color_space, gamma);
+    });
+
+    Ok(image)
+}
+
+// Supersampling is done in linear light, i.e. in the CIE XYZ space unless the
+// source color space is already linear, but unlike mip levels the result is
+// converted back into the source color space.
+fn supersample_image(
+    image: &Image,
+    width: u32,
+    height: u32,
+    samples: u32,
+    color_space: ColorSpace,
+    gamma: Option<f64>,
+) -> Result<Image, Error> {
+    if color_space.is_linear() {
+        return image.supersample(width, height, samples);
+    }
+
+    let mut linear = image.clone();
+
+    linear.pixels.par_iter_mut().for_each(|pixel| {
+        *pixel = pixel.convert_into_cie_xyz(color_space, gamma);
+    });
+
+    let mut image = linear.supersample(width, height, samples)?;
+
+    image.pixels.par_iter_mut().for_each(|pixel| {
+        *pixel = pixel.convert_from_cie_xyz(color_space, gamma);
+    });
+
+    Ok(image)
+}
+
+// Mip levels are always filtered in linear light, so pixel data in a color space
+// which is not linear is filtered in the CIE XYZ space instead.
+fn generate_mip_levels(image: &Image, color_space: ColorSpace, gamma: Option<f64>) -> Vec<Image> {
+    let mut linear = image.clone();
+
+    if !color_space.is_linear() {
+        linear.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = pixel.convert_into_cie_xyz(color_space, gamma);
+        });
+    }
+
+    let mut levels = vec![];
+
+    while let Some(downsampled) = linear.downsample() {
+        let mut level = downsampled.clone();
+
+        if !color_space.is_linear() {
+            level.pixels.par_iter_mut().for_each(|pixel| {
+                *pixel = pixel.convert_from_cie_xyz(color_space, gamma);
+            });
+        }
+
+        levels.push(level);
+        linear = downsampled;
+    }
+
+    levels
+}
+
+// Counts the pixels with a stored channel outside the range of the data format,
+// which would be clamped when stored. Infinities and NaNs are not counted.
+fn count_clamped_pixels(image: &Image, data_format: DataFormat) -> usize {
+    let min = min_representable_value(data_format);
+    let max = max_representable_value(data_format);
+    let channels = data_format.channel_count();
+
+    image
+        .pixels
+        .par_iter()
+        .filter(|pixel| {
+            pixel.to_array()[..channels]
+                .iter()
+                .any(|&x| x.is_finite() && (x < min || x > max))
+        })
+        .count()
+}
+
+fn max_representable_value(data_format: DataFormat) -> f64 {
+    match data_format {
+        DataFormat::R32F | DataFormat::RG32F | DataFormat::RGBA32F | DataFormat::RGBE8 => {
+            f32::MAX as f64
+        }
+        DataFormat::R16F | DataFormat::RG16F | DataFormat::RGBA16F | DataFormat::PackedR16F => {
+            65504.0
+        }
+        DataFormat::R32U | DataFormat::RGBA32U => u32::MAX as f64,
+        DataFormat::RGB9E5 => 65408.0,
+        DataFormat::R11G11B10F => 65024.0,
+        DataFormat::R8
+        | DataFormat::PackedR8
+        | DataFormat::RG8
+        | DataFormat::RGBA8
+        | DataFormat::SRGBA8
+        | DataFormat::RGBA8Snorm
+        | DataFormat::RG8Snorm
+        | DataFormat::BC1
+        | DataFormat::PackedR1
+        | DataFormat::PackedR4 => 1.0,
+    }
+}
+
+fn min_representable_value(data_format: DataFormat) -> f64 {
+    match data_format {
+        DataFormat::R32F
+        | DataFormat::RG32F
+        | DataFormat::RGBA32F
+        | DataFormat::R16F
+        | DataFormat::RG16F
+        | DataFormat::RGBA16F
+        | DataFormat::PackedR16F => -max_representable_value(data_format),
+        DataFormat::RGBA8Snorm | DataFormat::RG8Snorm => -1.0,
+        DataFormat::R32U
+        | DataFormat::RGBA32U
+        | DataFormat::RGBE8
+        | DataFormat::RGB9E5
+        | DataFormat::R11G11B10F
+        | DataFormat::R8
+        | DataFormat::PackedR8
+        | DataFormat::RG8
+        | DataFormat::RGBA8
+        | DataFormat::SRGBA8
+        | DataFormat::BC1
+        | DataFormat::PackedR1
+        | DataFormat::PackedR4 => 0.0,
+    }
+}
+
+fn is_single_channel_format(data_format: DataFormat) -> bool {
+    data_format.channel_count() == 1
+}
+
+fn is_unorm8_format(data_format: DataFormat) -> bool {
+    matches!(
+        data_format,
+        DataFormat::R8
+            | DataFormat::PackedR8
+            | DataFormat::RG8
+            | DataFormat::RGBA8
+            | DataFormat::SRGBA8
+    )
+}
+
+// Returns the number of palette indices which can be stored in a data format.
+fn palette_capacity(data_format: DataFormat) -> Option<usize> {
+    match data_format {
+        DataFormat::R8 | DataFormat::PackedR8 => Some(256),
+        DataFormat::PackedR4 => Some(16),
+        DataFormat::PackedR1 => Some(2),
+        _ => None,
+    }
+}
+
+// Median cut repeatedly splits the box of colors with the widest range in any
+// channel at its median, weighted by pixel count, until there are enough boxes
+// or none can be split further. Each box is averaged into one palette color,
+// and pixels are replaced by the index of the nearest palette color, scaled so
+// that it is stored exactly. Colors are compared as they are stored, in 8 bits.
+fn quantize_image(
+    image: &mut Image,
+    colors: usize,
+    capacity: usize,
+    rounding: Rounding,
+) -> Result<Image, Error> {
+    let mut histogram = HashMap::new();
+
+    for pixel in &image.pixels {
+        *histogram
+            .entry(pixel.to_array().map(|x| unorm8(x, rounding)))
+            .or_insert(0u64) += 1;
+    }
+
+    let mut all_colors: Vec<_> = histogram.into_iter().collect();
+    all_colors.sort_unstable();
+
+    let mut boxes = vec![all_colors];
+
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(index, colors)| {
+                (0..4).map(move |channel| {
+                    let values = colors.iter().map(|(color, _)| color[channel]);
+                    let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                    (range, index, channel)
+                })
+            })
+            .max_by_key(|&(range, index, channel)| (range, Reverse(index), Reverse(channel)));
+
+        let (index, channel) = match widest {
+            Some((_, index, channel)) => (index, channel),
+            None => break,
+        };
+
+        let colors = &mut boxes[index];
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut running = 0;
+
+        let median = colors
+            .iter()
+            .position(|(_, count)| {
+                running += count;
+                running * 2 >= total
+            })
+            .map_or(1, |position| position + 1)
+            .clamp(1, colors.len() - 1);
+
+        let upper = colors.split_off(median);
+        boxes.insert(index + 1, upper);
+    }
+
+    let mut palette = Image::new(boxes.len() as u32, 1)?;
+
+    for (colors, pixel) in boxes.iter().zip(&mut palette.pixels) {
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut sums = [0u64; 4];
+
+        for (color, count) in colors {
+            for (sum, &value) in sums.iter_mut().zip(color) {
+                *sum += value as u64 * count;
+            }
+        }
+
+        *pixel = Pixel::from_array(sums.map(|sum| ((sum + total / 2) / total) as f64 / 255.0));
+    }
+
+    let entries: Vec<_> = palette
+        .pixels
+        .iter()
+        .map(|pixel| pixel.to_array().map(|x| unorm8(x, rounding)))
+        .collect();
+
+    let indices: HashMap<_, _> = boxes
+        .iter()
+        .flatten()
+        .map(|&(color, _)| {
+            let distance = |entry: &[u8; 4]| {
+                (0..4)
+                    .map(|i| (color[i] as i32 - entry[i] as i32).pow(2))
+                    .sum::<i32>()
+            };
+
+            let index = (0..entries.len())
+                .min_by_key(|&index| distance(&entries[index]))
+                .unwrap_or(0);
+
+            (color, index)
+        })
+        .collect();
+
+    let max_index = (capacity - 1).max(1) as f64;
+
+    image.pixels.par_iter_mut().for_each(|pixel| {
+        let index = indices[&pixel.to_array().map(|x| unorm8(x, rounding))];
+
+        *pixel = Pixel::from_array([index as f64 / max_index, 0.0, 0.0, 1.0]);
+    });
+
+    Ok(palette)
+}
+
+fn dither_pixels(image: &mut Image, rounding: Rounding) {
+    for channel in 0..4 {
+        dither_channel(image, rounding, channel);
+    }
+}
+
+// Floyd-Steinberg error diffusion; each value is snapped to its 8-bit level, or
+// to the center of its quantization step when the store functions truncate, so
+// that the level is stored unchanged regardless of the rounding mode.
+fn dither_channel(image: &mut Image, rounding: Rounding, channel: usize) {
+    let width = image.width as usize;
+    let height = image.height as usize;
+
+    let pixels = &mut image.pixels;
+
+    for y in 0..height {
+        for x in 0..width {
+            let value = &mut pixels[y * width + x][channel];
+
+            let clamped = value.clamp(0.0, 1.0);
+            let level = (clamped * 255.0).round();
+            let error = clamped - level / 255.0;
+
+            *value = match rounding {
+                Rounding::Truncate => (level + 0.5) / 255.0,
+                Rounding::Round | Rounding::RoundEven => level / 255.0,
+            };
+
+            if x + 1 < width {
+                pixels[y * width + x + 1][channel] += error * 7.0 / 16.0;
+            }
+
+            if y + 1 < height {
+                if x > 0 {
+                    pixels[(y + 1) * width + x - 1][channel] += error * 3.0 / 16.0;
+                }
+
+                pixels[(y + 1) * width + x][channel] += error * 5.0 / 16.0;
+
+                if x + 1 < width {
+                    pixels[(y + 1) * width + x + 1][channel] += error / 16.0;
+                }
+            }
+        }
+    }
+}
+
+// Output
+
+fn create_output(path: &Path) -> Result<Box<dyn Write>, Error> {
+    if path == Path::new("-") {
+        Ok(Box::new(stdout().lock()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+// The pixel data is streamed to the output without being buffered in memory.
+// An embedded header needs the checksum of the pixel data up front, so space
+// for it is reserved in files and filled in at the end, while for standard
+// output, which cannot seek, the checksum is computed in a first pass.
+fn stream_output(
+    output_file: &Path,
+    embed_header: bool,
+    mut store: impl FnMut(&mut dyn Write) -> Result<u32, Error>,
+    header: impl Fn(u32) -> Result<Header, Error>,
+) -> Result<u32, Error> {
+    if output_file == Path::new("-") {
+        let mut output = BufWriter::new(stdout().lock());
+
+        if embed_header {
+            let checksum = store(&mut std::io::sink())?;
+            output.write_all(header(checksum)?.as_bytes())?;
+        }
+
+        let checksum = store(&mut output)?;
+        output.flush()?;
+
+        Ok(checksum)
+    } else {
+        let mut file = File::create(output_file)?;
+
+        if embed_header {
+            file.seek(SeekFrom::Start(size_of::<Header>() as u64))?;
+        }
+
+        let mut output = BufWriter::new(&mut file);
+        let checksum = store(&mut output)?;
+        output.flush()?;
+        drop(output);
+
+        if embed_header {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(header(checksum)?.as_bytes())?;
+        }
+
+        Ok(checksum)
+    }
+}
+
+fn store_levels<W: Write>(
+    args: &Arguments,
+    data_format: DataFormat,
+    levels: &[Image],
+    writer: W,
+) -> Result<u32, Error> {
+    let mut writer = ChecksumWriter::new(writer);
+
+    for level in levels {
+        store_pixels(
+            level,
+            data_format,
+            args.row_align,
+            args.byte_order,
+            args.rounding,
+            &mut writer,
+        )?;
+    }
+
+    writer.finish()
+}
+
+// The row alignment of the data format is used unless an override is given.
+fn store_pixels<W: Write>(
+    image: &Image,
+    data_format: DataFormat,
+    row_alignment: Option<usize>,
+    byte_order: Endianness,
+    rounding: Rounding,
+    writer: W,
+) -> Result<(), Error> {
+    if image.pixels.len() != image.width as usize * image.height as usize {
+        bail!(
+            "image has {} pixels but its dimensions are {}x{}",
+            image.pixels.len(),
+            image.width,
+            image.height
+        );
+    }
+
+    let row_alignment = row_alignment.unwrap_or_else(|| data_format.row_alignment());
+
+    match byte_order {
+        Endianness::Little => store_pixels_with_byte_order::<LE, W>(
+            image,
+            data_format,
+            row_alignment,
+            rounding,
+            writer,
+        ),
+        Endianness::Big => store_pixels_with_byte_order::<BE, W>(
+            image,
+            data_format,
+            row_alignment,
+            rounding,
+            writer,
+        ),
+    }
+}
+
+fn store_pixels_with_byte_order<B: ByteOrder, W: Write>(
+    image: &Image,
+    data_format: DataFormat,
+    row_alignment: usize,
+    rounding: Rounding,
+    writer: W,
+) -> Result<(), Error> {
+    match data_format {
+        DataFormat::R32F => store_rows(image, row_alignment, writer, store_r32f_row::<B>),
+        DataFormat::RG32F => store_rows(image, row_alignment, writer, store_rg32f_row::<B>),
+        DataFormat::RGBA32F => store_rows(image, row_alignment, writer, store_rgba32f_row::<B>),
+        DataFormat::R8 => store_rows(image, row_alignment, writer, |row, writer| {
+            store_r8_row(row, rounding, writer)
+        }),
+        DataFormat::PackedR8 => store_rows(image, row_alignment, writer, |row, writer| {
+            store_r8_row(row, rounding, writer)
+        }),
+        DataFormat::R16F => store_rows(image, row_alignment, writer, store_r16f_row::<B>),
+        DataFormat::RG16F => store_rows(image, row_alignment, writer, store_rg16f_row::<B>),
+        DataFormat::RGBA16F => store_rows(image, row_alignment, writer, store_rgba16f_row::<B>),
+        DataFormat::PackedR16F => store_rows(image, row_alignment, writer, store_r16f_row::<B>),
+        DataFormat::RGBE8 => store_rows(image, row_alignment, writer, store_rgbe8_row),
+        DataFormat::RGBA8 => store_rows(image, row_alignment, writer, |row, writer| {
+            store_rgba8_row(row, rounding, writer)
+        }),
+        DataFormat::BC1 => store_bc1_pixels(image, rounding, writer),
+        DataFormat::RG8 => store_rows(image, row_alignment, writer, |row, writer| {
+            store_rg8_row(row, rounding, writer)
+        }),
+        DataFormat::RGBA8Snorm => store_rows(image, row_alignment, writer, |row, writer| {
+            store_rgba8_snorm_row(row, rounding, writer)
+        }),
+        DataFormat::RG8Snorm => store_rows(image, row_alignment, writer, |row, writer| {
+            store_rg8_snorm_row(row, rounding, writer)
+        }),
+        DataFormat::R32U => store_rows(image, row_alignment, writer, store_r32u_row::<B>),
+        DataFormat::RGBA32U => store_rows(image, row_alignment, writer, store_rgba32u_row::<B>),
+        DataFormat::SRGBA8 => store_rows(image, row_alignment, writer, |row, writer| {
+            store_rgba8_row(row, rounding, writer)
+        }),
+        DataFormat::RGB9E5 => store_rows(image, row_alignment, writer, store_rgb9e5_row::<B>),
+        DataFormat::R11G11B10F => {
+            store_rows(image, row_alignment, writer, store_r11g11b10f_row::<B>)
+        }
+        DataFormat::PackedR1 => store_rows(image, row_alignment, writer, store_packed_r1_row),
+        DataFormat::PackedR4 => store_rows(image, row_alignment, writer, |row, writer| {
+            store_packed_r4_row(row, rounding, writer)
+        }),
+    }
+}
+
+// Rows are encoded independently into separate buffers in parallel, and then
+// written out in order, so the output is the same as if written serially. Each
+// row is padded to the row alignment after being encoded. Rows are processed
+// in bands to bound the memory used by the row buffers.
+fn store_rows<W: Write>(
+    image: &Image,
+    row_alignment: usize,
+    mut writer: W,
+    store_row: impl Fn(&[Pixel], &mut Vec<u8>) -> Result<(), Error> + Sync,
+) -> Result<(), Error> {
+    const BAND_HEIGHT: usize = 256;
+
+    let rows: Vec<_> = image.rows().collect();
+
+    for band in rows.chunks(BAND_HEIGHT) {
+        let band = band
+            .par_iter()
+            .map(|row| {
+                let mut bytes = vec![];
+                store_row(row, &mut bytes)?;
+                bytes.resize(bytes.len().div_ceil(row_alignment) * row_alignment, 0);
+                Ok(bytes)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for row in band {
+            writer.write_all(&row)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn store_r32f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_f32::<B>(pixel.r as f32)?;
+    }
+
+    Ok(())
+}
+
+fn store_rg32f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_f32::<B>(pixel.r as f32)?;
+        writer.write_f32::<B>(pixel.g as f32)?;
+    }
+
+    Ok(())
+}
+
+fn store_rgba32f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_f32::<B>(pixel.r as f32)?;
+        writer.write_f32::<B>(pixel.g as f32)?;
+        writer.write_f32::<B>(pixel.b as f32)?;
+        writer.write_f32::<B>(pixel.a as f32)?;
+    }
+
+    Ok(())
+}
+
+fn store_r8_row(row: &[Pixel], rounding: Rounding, writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u8(unorm8(pixel.r, rounding))?;
+    }
+
+    Ok(())
+}
+
+// The 1-bit format is thresholded at 0.5 regardless of the rounding mode, so
+// that a value of exactly 0.5 is always stored as one.
+fn store_packed_r1_row(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    store_packed_row(row, 1, |x| (x >= 0.5) as u8, writer)
+}
+
+fn store_packed_r4_row(
+    row: &[Pixel],
+    rounding: Rounding,
+    writer: &mut Vec<u8>,
+) -> Result<(), Error> {
+    store_packed_row(
+        row,
+        4,
+        |x| rounding.apply(x.clamp(0.0, 1.0) * 15.0) as u8,
+        writer,
+    )
+}
+
+// Pixels are packed MSB-first, and the last byte of the row is zero-padded so
+// that the next row starts on a byte boundary.
+fn store_packed_row(
+    row: &[Pixel],
+    bits_per_pixel: usize,
+    level: impl Fn(f64) -> u8,
+    writer: &mut Vec<u8>,
+) -> Result<(), Error> {
+    for pixels in row.chunks(8 / bits_per_pixel) {
+        let mut byte = 0;
+
+        for (index, pixel) in pixels.iter().enumerate() {
+            byte |= level(pixel.r) << (8 - bits_per_pixel * (index + 1));
+        }
+
+        writer.write_u8(byte)?;
+    }
+
+    Ok(())
+}
+
+// NaN is stored as zero and infinities are clamped to the largest finite half
+// value of the same sign, i.e. +/-65504, while every finite half value survives
+// the round trip through f64 unchanged. Half-float pixel data that needs no
+// processing at all is copied instead, see is_half_float_copy.
+fn safe_f64_to_f16(x: f64) -> f16 {
+    if x.is_nan() {
+        f16::from_f64(0.0)
+    } else {
+        f16::from_f64(x.clamp(-65504.0, 65504.0))
+    }
+}
+
+fn store_r16f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.r).to_bits())?;
+    }
+
+    Ok(())
+}
+
+fn store_rg16f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.r).to_bits())?;
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.g).to_bits())?;
+    }
+
+    Ok(())
+}
+
+fn store_rgba16f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.r).to_bits())?;
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.g).to_bits())?;
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.b).to_bits())?;
+        writer.write_u16::<B>(safe_f64_to_f16(pixel.a).to_bits())?;
+    }
+
+    Ok(())
+}
+
+fn store_rgbe8_row(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        let v = pixel.r.max(pixel.g).max(pixel.b);
+
+        if v < 1e-32 {
+            writer.write_u8(0)?;
+            writer.write_u8(0)?;
+            writer.write_u8(0)?;
+            writer.write_u8(0)?;
+        } else {
+            let (f, e) = frexp(v);
+
+            let r_byte = (pixel.r * f * 256.0 / v).clamp(0.0, 255.0) as u8;
+            let g_byte = (pixel.g * f * 256.0 / v).clamp(0.0, 255.0) as u8;
+            let b_byte = (pixel.b * f * 256.0 / v).clamp(0.0, 255.0) as u8;
+
+            writer.write_u8(r_byte)?;
+            writer.write_u8(g_byte)?;
+            writer.write_u8(b_byte)?;
+            writer.write_u8((e + 128).clamp(0, 255) as u8)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Returns (f, e) such that x = f * 2^e with 0.5 <= |f| < 1, like C's frexp,
+// by reading the exponent and mantissa directly from the IEEE-754 bits.
+fn frexp(x: f64) -> (f64, i32) {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+
+    if exponent == 0 {
+        if x == 0.0 {
+            return (x, 0);
+        }
+
+        let (f, e) = frexp(x * 2f64.powi(64));
+        return (f, e - 64);
+    }
+
+    if exponent == 0x7ff {
+        return (x, 0);
+    }
+
+    let mantissa = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+
+    (mantissa, exponent - 1022)
+}
+
+// Packs the channels as described by the EXT_texture_shared_exponent spec, with
+// 9-bit mantissas and a 5-bit exponent biased by 15 shared by all channels.
+fn store_rgb9e5_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    const MAX: f64 = 65408.0;
+
+    let clamp = |x: f64| if x.is_nan() { 0.0 } else { x.clamp(0.0, MAX) };
+
+    for pixel in row {
+        let (r, g, b) = (clamp(pixel.r), clamp(pixel.g), clamp(pixel.b));
+
+        let max = r.max(g).max(b);
+        let mut exponent = max.log2().floor().max(-16.0) as i32 + 16;
+
+        if (max / 2f64.powi(exponent - 24) + 0.5).floor() == 512.0 {
+            exponent += 1;
+        }
+
+        let scale = 2f64.powi(exponent - 24);
+        let mantissa = |x: f64| (x / scale + 0.5).floor() as u32;
+
+        writer.write_u32::<B>(
+            mantissa(r) | mantissa(g) << 9 | mantissa(b) << 18 | (exponent as u32) << 27,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Packs red and green as 11-bit unsigned floats with 6-bit mantissas and blue as
+// a 10-bit unsigned float with a 5-bit mantissa, all with a 5-bit exponent biased
+// by 15, as in the packed float formats of graphics APIs. Negatives become zero.
+fn store_r11g11b10f_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u32::<B>(
+            small_float(pixel.r, 6) | small_float(pixel.g, 6) << 11 | small_float(pixel.b, 5) << 22,
+        )?;
+    }
+
+    Ok(())
+}
+
+// The mantissa is rounded to the nearest value, which may carry into the exponent.
+fn small_float(x: f64, mantissa_bits: i32) -> u32 {
+    let max = (2.0 - 2f64.powi(-mantissa_bits)) * 2f64.powi(15);
+    let x = if x.is_nan() { 0.0 } else { x.clamp(0.0, max) };
+
+    let exponent = x.log2().floor().max(-14.0) as i32;
+    let mantissa = (x / 2f64.powi(exponent - mantissa_bits) + 0.5).floor() as u32;
+
+    (((exponent + 15) as u32) << mantissa_bits) + mantissa - (1 << mantissa_bits)
+}
+
+fn unorm8(x: f64, rounding: Rounding) -> u8 {
+    rounding.apply(x.clamp(0.0, 1.0) * 255.0) as u8
+}
+
+fn store_rgba8_row(row: &[Pixel], rounding: Rounding, writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u8(unorm8(pixel.r, rounding))?;
+        writer.write_u8(unorm8(pixel.g, rounding))?;
+        writer.write_u8(unorm8(pixel.b, rounding))?;
+        writer.write_u8(unorm8(pixel.a, rounding))?;
+    }
+
+    Ok(())
+}
+
+fn store_bc1_pixels<W: Write>(
+    image: &Image,
+    rounding: Rounding,
+    mut writer: W,
+) -> Result<(), Error> {
+    if !image.width.is_multiple_of(4) || !image.height.is_multiple_of(4) {
+        return Err(ConvertError::UnsupportedCombination(
+            "BC1: image dimensions must be a multiple of 4".to_owned(),
+        )
+        .into());
+    }
+
+    let mut rgba = Vec::with_capacity(image.width as usize * image.height as usize * 4);
+
+    for pixel in &image.pixels {
+        rgba.push(unorm8(pixel.r, rounding));
+        rgba.push(unorm8(pixel.g, rounding));
+        rgba.push(unorm8(pixel.b, rounding));
+        rgba.push(255);
+    }
+
+    let mut compressed =
+        vec![0; Format::Bc1.compressed_size(image.width as usize, image.height as usize)];
+
+    Format::Bc1.compress(
+        &rgba,
+        image.width as usize,
+        image.height as usize,
+        squish::Params {
+            algorithm: Algorithm::IterativeClusterFit,
+            weights: squish::COLOUR_WEIGHTS_PERCEPTUAL,
+            weigh_colour_by_alpha: false,
+        },
+        &mut compressed,
+    );
+
+    Ok(writer.write_all(&compressed)?)
+}
+
+fn store_rg8_row(row: &[Pixel], rounding: Rounding, writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u8(unorm8(pixel.r, rounding))?;
+        writer.write_u8(unorm8(pixel.g, rounding))?;
+    }
+
+    Ok(())
+}
+
+fn snorm8(x: f64, rounding: Rounding) -> i8 {
+    rounding
+        .apply(x.clamp(-1.0, 1.0) * 128.0)
+        .clamp(-128.0, 127.0) as i8
+}
+
+fn store_rgba8_snorm_row(
+    row: &[Pixel],
+    rounding: Rounding,
+    writer: &mut Vec<u8>,
+) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_i8(snorm8(pixel.r, rounding))?;
+        writer.write_i8(snorm8(pixel.g, rounding))?;
+        writer.write_i8(snorm8(pixel.b, rounding))?;
+        writer.write_i8(snorm8(pixel.a, rounding))?;
+    }
+
+    Ok(())
+}
+
+fn store_rg8_snorm_row(
+    row: &[Pixel],
+    rounding: Rounding,
+    writer: &mut Vec<u8>,
+) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_i8(snorm8(pixel.r, rounding))?;
+        writer.write_i8(snorm8(pixel.g, rounding))?;
+    }
+
+    Ok(())
+}
+
+// Channel values are interpreted as integers rather than normalized values, so
+// they are truncated towards zero and saturate to the range of a u32.
+fn store_r32u_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u32::<B>(pixel.r as u32)?;
+    }
+
+    Ok(())
+}
+
+fn store_rgba32u_row<B: ByteOrder>(row: &[Pixel], writer: &mut Vec<u8>) -> Result<(), Error> {
+    for pixel in row {
+        writer.write_u32::<B>(pixel.r as u32)?;
+        writer.write_u32::<B>(pixel.g as u32)?;
+        writer.write_u32::<B>(pixel.b as u32)?;
+        writer.write_u32::<B>(pixel.a as u32)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::fs;
+
+    fn rgba_png(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        image::png::PNGEncoder::new(&mut bytes)
+            .encode(data, width, height, image::ColorType::RGBA(8))
+            .unwrap();
+
+        bytes
+    }
+
+    fn gradient_png(width: u32, height: u32) -> Vec<u8> {
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+
+        rgba_png(width, height, &data)
+    }
+
+    fn arguments(extra: &[&str]) -> Arguments {
+        let args = ["img2raw", "source", "output"];
+        Arguments::from_iter_safe(args.iter().chain(extra)).unwrap()
+    }
+
+    fn grayscale_png(bit_depth: png::BitDepth, data: &[u8], width: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, width, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(bit_depth);
+
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+        }
+
+        bytes
+    }
+
+    // Each test gets its own directory since tests run concurrently.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("img2raw-unit-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Runs the tool on a source file holding the given bytes, returning the bytes
+    // of the output file.
+    fn run_tool(name: &str, source: &[u8], extra: &[&str]) -> Result<Vec<u8>, Error> {
+        let dir = scratch_dir(name);
+        let source_file = dir.join("source");
+        let output_file = dir.join("output");
+
+        fs::write(&source_file, source)?;
+
+        let mut args = vec![
+            "img2raw".into(),
+            source_file.into_os_string(),
+            output_file.clone().into_os_string(),
+        ];
+        args.extend(extra.iter().map(OsString::from));
+
+        let result =
+            run(&Arguments::from_iter_safe(args)?).and_then(|()| Ok(fs::read(&output_file)?));
+        fs::remove_dir_all(&dir)?;
+        result
+    }
+
+    #[test]
+    fn dithering_a_shallow_ramp_varies_the_output() {
+        let (width, height) = (64, 16);
+        let mut image = Image::new(width as u32, height).unwrap();
+
+        // The ramp only spans two 8-bit levels, so most columns would otherwise
+        // be stored as the same value.
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            let x = (i % width) as f64 / (width - 1) as f64;
+            pixel.r = 0.5 + x * 2.0 / 255.0;
+        }
+
+        let column_sums = |image: &Image| {
+            let mut bytes = vec![];
+            store_pixels(
+                image,
+                DataFormat::R8,
+                None,
+                Endianness::Little,
+                Rounding::Round,
+                &mut bytes,
+            )
+            .unwrap();
+
+            let mut sums: Vec<u32> = (0..width)
+                .map(|x| bytes[x..].iter().step_by(width).map(|&b| b as u32).sum())
+                .collect();
+            sums.dedup();
+            sums
+        };
+
+        let plain = column_sums(&image);
+        dither_pixels(&mut image, Rounding::Round);
+        let dithered = column_sums(&image);
+
+        assert!(plain.len() <= 3);
+        assert!(dithered.len() > plain.len() * 3);
+    }
+
+    #[test]
+    fn flipped_rows_are_stored_bottom_up() {
+        let mut image = Image::new(1, 2).unwrap();
+        image.pixels[0] = Pixel {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        };
+        image.pixels[1] = Pixel {
+            r: 0.0,
+            g: 0.0,
+            b: 1.0,
+            a: 1.0,
+        };
+
+        image.flip_vertically();
+
+        let mut bytes = vec![];
+        store_pixels(
+            &image,
+            DataFormat::RGBA8,
+            None,
+            Endianness::Little,
+            Rounding::Round,
+            &mut bytes,
+        )
+        .unwrap();
+        assert_eq!(bytes, [0, 0, 255, 255, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn box_filter_averages_covered_pixels() {
+        let mut image = Image::new(4, 4).unwrap();
+
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            pixel.r = i as f64;
+            pixel.a = 1.0;
+        }
+
+        let resized = image.resize(2, 2, ResizeFilter::Box).unwrap();
+
+        // The top left output pixel covers source pixels 0, 1, 4 and 5.
+        let expected = [2.5, 4.5, 10.5, 12.5];
+
+        for (pixel, &red) in resized.pixels.iter().zip(&expected) {
+            assert!((pixel.r - red).abs() < 1e-12, "{} != {}", pixel.r, red);
+            assert!((pixel.a - 1.0).abs() < 1e-12);
+        }
+    }
+
+    fn assert_close(actual: [f64; 4], expected: [f64; 4], tolerance: f64) {
+        for (x, y) in actual.iter().zip(&expected) {
+            assert!((x - y).abs() < tolerance, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn convert(pixel: [f64; 4], source: ColorSpace, output: ColorSpace) -> [f64; 4] {
+        let [r, g, b, a] = pixel;
+        let pixel = Pixel { r, g, b, a }
+            .convert_into_cie_xyz(source, None)
+            .convert_from_cie_xyz(output, None);
+
+        [pixel.r, pixel.g, pixel.b, pixel.a]
+    }
+
+    #[test]
+    fn hsv_pure_hue_converts_to_red() {
+        let red = convert(
+            [0.0, 1.0, 1.0, 1.0],
+            ColorSpace::HSV,
+            ColorSpace::LinearSRGB,
+        );
+        assert_close(red, [1.0, 0.0, 0.0, 1.0], 1e-3);
+    }
+
+    #[test]
+    fn adobe_rgb_green_has_reference_chromaticity() {
+        let [x, y, z, _] = convert(
+            [0.0, 1.0, 0.0, 1.0],
+            ColorSpace::AdobeRGB,
+            ColorSpace::CIEXYZ,
+        );
+
+        assert!((x / (x + y + z) - 0.21).abs() < 1e-3);
+        assert!((y / (x + y + z) - 0.71).abs() < 1e-3);
+    }
+
+    #[test]
+    fn neutral_ycbcr_converts_to_mid_gray() {
+        let gray = convert([0.5, 0.5, 0.5, 1.0], ColorSpace::YCbCr709, ColorSpace::SRGB);
+        assert_close(gray, [0.5, 0.5, 0.5, 1.0], 1e-3);
+    }
+
+    #[test]
+    fn d65_white_point_converts_to_neutral_lab() {
+        let [x, y, z] = D65_WHITE_POINT;
+        let lab = convert([x, y, z, 1.0], ColorSpace::CIEXYZ, ColorSpace::CIELAB);
+        assert_close(lab, [100.0, 0.0, 0.0, 1.0], 1e-4);
+    }
+
+    #[test]
+    fn mip_level_is_the_linear_average() {
+        let mut image = Image::new(2, 2).unwrap();
+
+        let values = [1.0, 0.0, 0.5, 0.25];
+
+        for (pixel, &value) in image.pixels.iter_mut().zip(&values) {
+            *pixel = Pixel {
+                r: value,
+                g: value,
+                b: value,
+                a: 1.0,
+            };
+        }
+
+        let levels = generate_mip_levels(&image, ColorSpace::SRGB, None);
+        assert_eq!(levels.len(), 1);
+        assert_eq!((levels[0].width, levels[0].height), (1, 1));
+
+        let average = values
+            .iter()
+            .map(|&value| Pixel::convert_from_gamma_srgb(value))
+            .sum::<f64>()
+            / 4.0;
+        let expected = Pixel::convert_into_gamma_srgb(average);
+
+        let pixel = levels[0].pixels[0];
+
+        for &value in &[pixel.r, pixel.g, pixel.b] {
+            assert!((value - expected).abs() < 1e-4, "{} != {}", value, expected);
+        }
+
+        assert!((pixel.a - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn premultiplied_white_is_scaled_by_alpha() {
+        let white = Pixel {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a: 0.5,
+        };
+
+        let pixel = white.premultiply(ColorSpace::LinearSRGB, None);
+        assert_eq!((pixel.r, pixel.g, pixel.b, pixel.a), (0.5, 0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn parallel_rows_match_serial_output() {
+        let (width, height) = (301, 600);
+        let mut image = Image::new(width, height).unwrap();
+
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            let x = (i % 997) as f64 / 996.0;
+            *pixel = Pixel {
+                r: x,
+                g: 1.0 - x,
+                b: x * x,
+                a: 0.5,
+            };
+        }
+
+        let mut parallel = vec![];
+        store_rows(
+            &image,
+            DataFormat::RGBA8.row_alignment(),
+            &mut parallel,
+            |row, writer| store_rgba8_row(row, Rounding::Round, writer),
+        )
+        .unwrap();
+
+        let mut serial = vec![];
+
+        for row in image.rows() {
+            store_rgba8_row(row, Rounding::Round, &mut serial).unwrap();
+        }
+
+        assert_eq!(parallel.len(), 4 * (width * height) as usize);
+        assert!(
+            parallel == serial,
+            "parallel output differs from serial output"
+        );
+    }
+
+    #[test]
+    fn single_threaded_output_matches_default() {
+        let source = gradient_png(64, 64);
+        let args = [
+            "--source-color-space",
+            "SRGB",
+            "--output-color-space",
+            "LinearSRGB",
+            "--format",
+            "RGBA16F",
+            "--mips",
+        ];
+
+        let default = run_tool("threads-default", &source, &args).unwrap();
+        let single = run_tool(
+            "threads-single",
+            &source,
+            &[&args[..], &["--threads", "1"]].concat(),
+        );
+
+        assert_eq!(single.unwrap(), default);
+    }
+
+    #[test]
+    fn batch_conversion_writes_every_output() {
+        let dir = scratch_dir("batch");
+        let output_dir = dir.join("output");
+        fs::create_dir_all(&output_dir).unwrap();
+
+        fs::write(dir.join("first.png"), gradient_png(4, 4)).unwrap();
+        fs::write(dir.join("second.png"), gradient_png(8, 2)).unwrap();
+
+        let args = Arguments::from_iter_safe(vec![
+            OsString::from("img2raw"),
+            dir.join("first.png").into_os_string(),
+            dir.join("second.png").into_os_string(),
+            "--output-dir".into(),
+            output_dir.clone().into_os_string(),
+            "--source-color-space".into(),
+            "SRGB".into(),
+            "--output-color-space".into(),
+            "SRGB".into(),
+            "--format".into(),
+            "RGBA8".into(),
+            "--quiet".into(),
+        ])
+        .unwrap();
+
+        run(&args).unwrap();
+
+        let first = fs::read(output_dir.join("first.raw")).unwrap();
+        let second = fs::read(output_dir.join("second.raw")).unwrap();
+        assert_eq!((first.len(), second.len()), (4 * 4 * 4, 8 * 2 * 4));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn out_of_range_values_are_only_clamped_on_request() {
+        // A single RGBE pixel with a red value of 2.0.
+        let mut source = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 1\n".to_vec();
+        source.extend_from_slice(&[128, 0, 0, 130]);
+
+        let stored = |name: &str, extra: &[&str]| {
+            let base = [
+                "--source-color-space",
+                "NonColor",
+                "--output-color-space",
+                "NonColor",
+                "--format",
+                "R32F",
+                "--quiet",
+            ];
+            run_tool(name, &source, &[&base[..], extra].concat()).unwrap()
+        };
+
+        assert_eq!(stored("clamp-off", &[]), 2.0f32.to_le_bytes());
+        assert_eq!(stored("clamp-on", &["--clamp"]), 1.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn huge_images_are_rejected() {
+        assert!(Image::new(u32::MAX, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn big_endian_floats_are_byte_reversed() {
+        let mut image = Image::new(3, 1).unwrap();
+        image.pixels[0] = Pixel {
+            r: 1.5,
+            a: 1.0,
+            ..Pixel::default()
+        };
+        image.pixels[1] = Pixel {
+            r: -0.1,
+            a: 1.0,
+            ..Pixel::default()
+        };
+        image.pixels[2] = Pixel {
+            r: 1e30,
+            a: 1.0,
+            ..Pixel::default()
+        };
+
+        let store = |byte_order| {
+            let mut bytes = vec![];
+            store_pixels(
+                &image,
+                DataFormat::R32F,
+                None,
+                byte_order,
+                Rounding::Round,
+                &mut bytes,
+            )
+            .unwrap();
+            bytes
+        };
+
+        let little = store(Endianness::Little);
+        let big = store(Endianness::Big);
+
+        for (little, big) in little.chunks(4).zip(big.chunks(4)) {
+            let reversed: Vec<u8> = little.iter().rev().copied().collect();
+            assert_eq!(big, &reversed[..]);
+        }
+    }
+
+    #[test]
+    fn finite_half_floats_survive_f64_round_trip() {
+        for bits in (0..0x7c00).chain(0x8000..0xfc00) {
+            let value = f16::from_bits(bits);
+            assert_eq!(safe_f64_to_f16(value.to_f64()).to_bits(), bits);
+        }
+
+        assert_eq!(safe_f64_to_f16(f64::INFINITY), f16::MAX);
+        assert_eq!(safe_f64_to_f16(f64::NEG_INFINITY), f16::MIN);
+        assert_eq!(safe_f64_to_f16(f64::NAN).to_bits(), 0);
+    }
+
+    #[test]
+    fn half_float_transcode_is_bit_exact() {
+        // Every half-float bit pattern, four channels per pixel, including the
+        // infinities and NaNs, which are only preserved by copying the pixel data.
+        let values: Vec<u16> = (0..=u16::MAX).collect();
+        let (width, height) = (128, values.len() as u32 / 4 / 128);
+        assert_eq!(width * height * 4, values.len() as u32);
+
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let header = HeaderBuilder::new()
+            .color_space(ColorSpace::NonColor)
+            .data_format(DataFormat::RGBA16F)
+            .dimensions(width, height)
+            .checksum(Header::compute_checksum(&data))
+            .build();
+
+        let mut source = header.as_bytes().to_vec();
+        source.extend_from_slice(&data);
+
+        let args = [
+            "--transcode",
+            "--output-color-space",
+            "NonColor",
+            "--format",
+            "RGBA16F",
+        ];
+
+        let bytes = run_tool("half", &source, &args).unwrap();
+        assert!(bytes == data, "half-float values were not preserved");
+
+        // Flipping the image goes through f64, which still preserves finite values.
+        let args = [&args[..], &["--flip-y"]].concat();
+        let bytes = run_tool("half-flipped", &source, &args).unwrap();
+
+        let flipped: Vec<u8> = bytes
+            .chunks(width as usize * 8)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+
+        for (value, bytes) in values.iter().zip(flipped.chunks(2)) {
+            if value & 0x7c00 != 0x7c00 {
+                assert_eq!(bytes, value.to_le_bytes(), "{:04x}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn r32u_values_are_stored_as_integers() {
+        let mut image = Image::new(1, 1).unwrap();
+        image.pixels[0] = Pixel {
+            r: 70000.0,
+            a: 1.0,
+            ..Pixel::default()
+        };
+
+        let mut bytes = vec![];
+        store_pixels(
+            &image,
+            DataFormat::R32U,
+            None,
+            Endianness::Little,
+            Rounding::Round,
+            &mut bytes,
+        )
+        .unwrap();
+
+        assert_eq!(bytes, 70000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn frexp_splits_mantissa_and_exponent() {
+        assert_eq!(frexp(1.0), (0.5, 1));
+        assert_eq!(frexp(0.0), (0.0, 0));
+        assert_eq!(frexp(-3.0), (-0.75, 2));
+
+        for exponent in -1022..=1023 {
+            let x = f64::from_bits(((exponent + 1023) as u64) << 52);
+            assert_eq!(frexp(x), (0.5, exponent + 1));
+        }
+
+        for exponent in -1074..-1022 {
+            let x = f64::from_bits(1 << (exponent + 1074));
+            assert_eq!(frexp(x), (0.5, exponent + 1));
+        }
+
+        let subnormal = f64::from_bits(3);
+        assert_eq!(frexp(subnormal), (0.75, -1072));
+        assert_eq!(frexp(f64::MAX), (1.0 - f64::EPSILON / 2.0, 1024));
+        assert_eq!(frexp(f64::MIN_POSITIVE), (0.5, -1021));
+    }
+
+    fn hdr_pixels() -> Vec<Pixel> {
+        vec![
+            Pixel::from_array([0.001, 0.5, 1.0, 1.0]),
+            Pixel::from_array([100.0, 3.0, 0.25, 1.0]),
+            Pixel::from_array([60000.0, 1.0, 0.0, 1.0]),
+            Pixel::from_array([1.0 / 3.0, 2.0 / 3.0, 0.1, 1.0]),
+        ]
+    }
+
+    #[test]
+    fn rgb9e5_round_trips_within_shared_exponent_precision() {
+        let pixels = hdr_pixels();
+
+        let mut bytes = Vec::new();
+        store_rgb9e5_row::<LE>(&pixels, &mut bytes).unwrap();
+
+        let width = pixels.len() as u32;
+        let decoded = decode_pixels(DataFormat::RGB9E5, width, 1, &bytes).unwrap();
+
+        for (pixel, decoded) in pixels.iter().zip(&decoded) {
+            let max = pixel.r.max(pixel.g).max(pixel.b);
+
+            for channel in 0..3 {
+                let error = (decoded[channel] as f64 - pixel[channel]).abs();
+                assert!(error <= max / 256.0, "{:?} != {:?}", decoded, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn d65_white_point_converts_to_reference_xyy() {
+        let [x, y, z] = D65_WHITE_POINT;
+        let xyy = convert([x, y, z, 1.0], ColorSpace::CIEXYZ, ColorSpace::CIExyY);
+        assert_close(xyy, [0.3127, 0.3290, 1.0, 1.0], 1e-4);
+    }
+
+    #[test]
+    fn bgra_swizzle_swaps_red_and_blue() {
+        let data = [10, 20, 30, 40];
+        let args = [
+            "--source-color-space",
+            "NonColor",
+            "--output-color-space",
+            "NonColor",
+            "--format",
+            "RGBA8",
+            "--swizzle",
+            "bgra",
+        ];
+
+        let bytes = run_tool("swizzle", &rgba_png(1, 1, &data), &args).unwrap();
+        assert_eq!(bytes, [30, 20, 10, 40]);
+    }
+
+    #[test]
+    fn srgb_primaries_convert_to_reference_xyz() {
+        let primaries = [
+            ([1.0, 0.0, 0.0, 1.0], [0.4124, 0.2126, 0.0193, 1.0]),
+            ([0.0, 1.0, 0.0, 1.0], [0.3576, 0.7152, 0.1192, 1.0]),
+            ([0.0, 0.0, 1.0, 1.0], [0.1805, 0.0722, 0.9505, 1.0]),
+        ];
+
+        for &(rgb, xyz) in &primaries {
+            for &color_space in &[ColorSpace::SRGB, ColorSpace::LinearSRGB] {
+                assert_close(convert(rgb, color_space, ColorSpace::CIEXYZ), xyz, 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn d65_white_point_round_trips() {
+        let [x, y, z] = D65_WHITE_POINT;
+        let white = [x, y, z, 1.0];
+
+        for name in ColorSpace::names() {
+            let color_space: ColorSpace = name.parse().ok().unwrap();
+
+            if color_space == ColorSpace::HSV {
+                continue;
+            }
+
+            let pixel = convert(white, ColorSpace::CIEXYZ, color_space);
+            assert_close(convert(pixel, color_space, ColorSpace::CIEXYZ), white, 1e-4);
+        }
+    }
+
+    #[test]
+    fn reference_xyz_converts_to_linear_srgb_primaries() {
+        let primaries = [
+            ([0.4124, 0.2126, 0.0193, 1.0], [1.0, 0.0, 0.0, 1.0]),
+            ([0.3576, 0.7152, 0.1192, 1.0], [0.0, 1.0, 0.0, 1.0]),
+            ([0.1805, 0.0722, 0.9505, 1.0], [0.0, 0.0, 1.0, 1.0]),
+        ];
+
+        for &(xyz, rgb) in &primaries {
+            let pixel = convert(xyz, ColorSpace::CIEXYZ, ColorSpace::LinearSRGB);
+            assert_close(pixel, rgb, 1e-4);
+        }
+    }
+
+    #[test]
+    fn gamma_transfer_functions_round_trip() {
+        for index in 0..=1000 {
+            let x = index as f64 / 1000.0;
+
+            let srgb = Pixel::convert_into_gamma_srgb(Pixel::convert_from_gamma_srgb(x));
+            assert!((srgb - x).abs() < 1e-4, "{} != {}", srgb, x);
+
+            let adobe = Pixel::convert_into_gamma_adobe_rgb(Pixel::convert_from_gamma_adobe_rgb(x));
+            assert!((adobe - x).abs() < 1e-4, "{} != {}", adobe, x);
+        }
+    }
+
+    #[test]
+    fn dry_run_reports_size_without_writing() {
+        let dir = scratch_dir("dry-run");
+        let source = dir.join("source.png");
+        let output = dir.join("output.raw");
+
+        fs::write(&source, gradient_png(5, 3)).unwrap();
+
+        let args = Arguments::from_iter_safe(vec![
+            OsString::from("img2raw"),
+            source.into_os_string(),
+            output.clone().into_os_string(),
+            "--source-color-space".into(),
+            "SRGB".into(),
+            "--output-color-space".into(),
+            "SRGB".into(),
+            "--format".into(),
+            "RGBA8".into(),
+            "--header".into(),
+            "--dry-run".into(),
+            "--quiet".into(),
+        ])
+        .unwrap();
+
+        run(&args).unwrap();
+        assert!(!output.exists());
+
+        let size = output_size(&args, DataFormat::RGBA8, 5, 3).unwrap();
+        assert_eq!(size, (size_of::<Header>() + 5 * 3 * 4) as u64);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rows_are_slices_of_the_image_width() {
+        let mut image = Image::new(3, 2).unwrap();
+        image.pixels[3].r = 1.0;
+
+        let rows: Vec<_> = image.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.len() == 3));
+        assert_eq!(rows[1][0].r, 1.0);
+    }
+
+    #[test]
+    fn srgb_table_matches_transfer_function() {
+        let analytic = |x: f64| {
+            if x <= 0.040_45 {
+                x / 12.92
+            } else {
+                ((x + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        for index in 0..=255 {
+            let x = index as f64 / 255.0;
+            assert!((Pixel::convert_from_gamma_srgb(x) - analytic(x)).abs() < 1e-6);
+        }
+
+        let x = 0.123_456;
+        assert!((Pixel::convert_from_gamma_srgb(x) - analytic(x)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn packed_r1_packs_eight_pixels_per_byte() {
+        let values = [0.5, 0.49, 1.0, 0.0, 0.75, 0.25, 0.5001, -1.0];
+        let row: Vec<_> = values.iter().map(|&x| Pixel::from_array([x; 4])).collect();
+
+        let mut bytes = Vec::new();
+        store_packed_r1_row(&row, &mut bytes).unwrap();
+        assert_eq!(bytes, [0b1010_1010]);
+
+        let mut bytes = Vec::new();
+        store_packed_r1_row(&row[..5], &mut bytes).unwrap();
+        assert_eq!(bytes, [0b1010_1000]);
+    }
+
+    #[test]
+    fn r11g11b10f_round_trips_within_mantissa_precision() {
+        let pixels = hdr_pixels();
+
+        let mut bytes = Vec::new();
+        store_r11g11b10f_row::<LE>(&pixels, &mut bytes).unwrap();
+
+        let width = pixels.len() as u32;
+        let decoded = decode_pixels(DataFormat::R11G11B10F, width, 1, &bytes).unwrap();
+
+        // The blue channel has one fewer mantissa bit, and values below the
+        // smallest subnormal of 2^-20 are flushed to zero.
+        for (pixel, decoded) in pixels.iter().zip(&decoded) {
+            for (channel, mantissa_bits) in [(0, 6), (1, 6), (2, 5)] {
+                let tolerance = pixel[channel] * 0.5f64.powi(mantissa_bits) + 1e-6;
+                let error = (decoded[channel] as f64 - pixel[channel]).abs();
+                assert!(error <= tolerance, "{:?} != {:?}", decoded, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_images_are_resized_in_linear_light() {
+        let mut image = Image::new(2, 1).unwrap();
+        image.pixels[0] = Pixel::from_array([1.0, 1.0, 1.0, 1.0]);
+        image.pixels[1] = Pixel::from_array([0.0, 0.0, 0.0, 1.0]);
+
+        let resized = resize_image(
+            &image,
+            1,
+            1,
+            ResizeFilter::Filter(FilterType::Triangle),
+            ColorSpace::SRGB,
+            None,
+        )
+        .unwrap();
+
+        // White and black average to half the light intensity, not to 0.5 in sRGB.
+        let expected = Pixel::convert_into_gamma_srgb(0.5);
+        assert!((resized.pixels[0].g - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rounding_modes_quantize_ties() {
+        let x = 127.5 / 255.0;
+
+        assert_eq!(unorm8(x, Rounding::Round), 128);
+        assert_eq!(unorm8(x, Rounding::RoundEven), 128);
+        assert_eq!(unorm8(x, Rounding::Truncate), 127);
+        assert_eq!(unorm8(126.5 / 255.0, Rounding::RoundEven), 126);
+    }
+
+    // A 16x16 lossy WebP image made of a single skipped macroblock predicted
+    // from the default borders, so that its luma is uniformly 128.
+    const GRAY_WEBP: &[u8] = include_bytes!("../tests/fixtures/gray.webp");
+
+    #[test]
+    fn webp_luma_loads_into_red_channel() {
+        let (image, grayscale) = load_webp_image(GRAY_WEBP, true, 0, 0).unwrap();
+        assert!(grayscale);
+        assert_eq!((image.width, image.height), (16, 16));
+
+        for pixel in &image.pixels {
+            assert_eq!(
+                (pixel.r, pixel.g, pixel.b, pixel.a),
+                (128.0 / 255.0, 0.0, 0.0, 1.0)
+            );
+        }
+
+        assert!(load_webp_image(GRAY_WEBP, false, 0, 0).is_err());
+    }
+
+    #[test]
+    fn webp_fixture_bakes_its_luma() {
+        let args = |format| {
+            [
+                "--source-color-space",
+                "NonColor",
+                "--output-color-space",
+                "NonColor",
+                "--format",
+                format,
+            ]
+        };
+
+        let bytes = run_tool("webp-r8", GRAY_WEBP, &args("R8")).unwrap();
+        assert_eq!(bytes, vec![128; 16 * 16]);
+
+        assert!(run_tool("webp-rgba8", GRAY_WEBP, &args("RGBA8")).is_err());
+    }
+
+    #[test]
+    fn avif_images_are_reported_as_unsupported() {
+        let mut bytes = vec![0, 0, 0, 24];
+        bytes.extend_from_slice(b"ftypavif");
+        bytes.resize(24, 0);
+
+        let args = [
+            "--source-color-space",
+            "SRGB",
+            "--output-color-space",
+            "SRGB",
+            "--format",
+            "RGBA8",
+        ];
+
+        let error = run_tool("avif", &bytes, &args).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ConvertError::UnsupportedFileType(name)) if name == "AVIF"
+        ));
+    }
+
+    #[test]
+    fn library_encoder_matches_tool_output() {
+        let (width, height) = (5, 3);
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+
+        let pixels: Vec<[f32; 4]> = data
+            .chunks_exact(4)
+            .map(|pixel| [0, 1, 2, 3].map(|i| pixel[i] as f32 / 255.0))
+            .collect();
+
+        let source = rgba_png(width, height, &data);
+
+        for &data_format in &[DataFormat::RGBA8, DataFormat::R16F] {
+            let format = data_format.to_string();
+            let args = [
+                "--source-color-space",
+                "NonColor",
+                "--output-color-space",
+                "NonColor",
+                "--format",
+                &format,
+            ];
+
+            let bytes = run_tool("encoder", &source, &args).unwrap();
+
+            let mut expected = Vec::new();
+            img2raw::encode_pixels(&mut expected, data_format, width, &pixels).unwrap();
+
+            assert_eq!(bytes, expected, "{}", data_format);
+        }
+    }
+
+    #[test]
+    fn preallocated_output_length_matches_total_size() {
+        let source = gradient_png(16, 8);
+
+        for &data_format in &[DataFormat::RGBA32F, DataFormat::R8, DataFormat::BC1] {
+            let args = [
+                "--source-color-space",
+                "LinearSRGB",
+                "--output-color-space",
+                "LinearSRGB",
+                "--format",
+                &data_format.to_string(),
+                "--header",
+                "--preallocate",
+                "--quiet",
+            ];
+            let bytes = run_tool("preallocate", &source, &args).unwrap();
+
+            let expected = HeaderBuilder::new()
+                .data_format(data_format)
+                .dimensions(16, 8)
+                .build();
+
+            let length = size_of::<Header>() as u64 + expected.data_size().unwrap();
+            assert_eq!(bytes.len() as u64, length, "{}", data_format);
+        }
+    }
+
+    #[test]
+    fn passthrough_stores_srgb_channels_unchanged() {
+        let data: Vec<u8> = (0..64u32).map(|i| (i * 4 + 1) as u8).collect();
+
+        // The sRGB chunk, with its CRC, goes right after the IHDR chunk.
+        let mut source = rgba_png(4, 4, &data);
+        let srgb = [
+            0, 0, 0, 1, b's', b'R', b'G', b'B', 0, 0xAE, 0xCE, 0x1C, 0xE9,
+        ];
+        source.splice(33..33, srgb.iter().copied());
+        assert_eq!(detect_png_color_space(&source), Some(ColorSpace::SRGB));
+
+        let bytes = run_tool(
+            "passthrough",
+            &source,
+            &["--passthrough", "--format", "RGBA8", "--header"],
+        )
+        .unwrap();
+
+        let image = RawImage::parse(&bytes).unwrap();
+        assert_eq!(image.color_space(), ColorSpace::NonColor);
+        assert_eq!(image.pixels(), &data[..]);
+    }
+
+    #[test]
+    fn non_color_single_channel_stores_red_as_is() {
+        let args = [
+            "--source-color-space",
+            "NonColor",
+            "--output-color-space",
+            "NonColor",
+            "--format",
+            "R8",
+            "--quiet",
+        ];
+
+        let source = rgba_png(1, 1, &[255, 128, 64, 255]);
+        let bytes = run_tool("non-color-luma", &source, &args).unwrap();
+        assert_eq!(bytes[0], 255);
+    }
+
+    #[test]
+    fn grayscale_single_channel_is_not_weighted() {
+        let args = [
+            "--source-color-space",
+            "SRGB",
+            "--output-color-space",
+            "SRGB",
+            "--format",
+            "R8",
+            "--quiet",
+        ];
+
+        let source = grayscale_png(png::BitDepth::Eight, &[128], 1);
+        let bytes = run_tool("grayscale-luma", &source, &args).unwrap();
+        assert_eq!(bytes[0], 128);
+    }
+
+    #[test]
+    fn equal_luma_weights_average_channels() {
+        let weights = [1.0 / 3.0; 3];
+        let pixel = Pixel::from_array([1.0, 0.0, 0.0, 1.0]);
+        let luma = pixel.luma(ColorSpace::LinearSRGB, None, weights);
+
+        assert!((luma.r - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn grayscale_gradient_loads_into_red_channel() {
+        let data: Vec<u8> = (0..=255).collect();
+        let bytes = grayscale_png(png::BitDepth::Eight, &data, 256);
+
+        let (image, grayscale) = load_png_image(&bytes, true, 0).unwrap();
+        assert!(grayscale);
+
+        for (value, pixel) in data.iter().zip(&image.pixels) {
+            assert_eq!(pixel.r, *value as f64 / 255.0);
+            assert_eq!((pixel.g, pixel.b), (0.0, 0.0));
+        }
+
+        let (image, grayscale) = load_png_image(&bytes, false, 0).unwrap();
+        assert!(!grayscale);
+        assert_eq!(image.pixels[128].g, image.pixels[128].r);
+    }
+
+    #[test]
+    fn grayscale_16_bit_gradient_loads_into_red_channel() {
+        let values: Vec<u16> = (0..256).map(|x| x * 257).collect();
+        let data: Vec<u8> = values.iter().flat_map(|x| x.to_be_bytes()).collect();
+        let bytes = grayscale_png(png::BitDepth::Sixteen, &data, 256);
+
+        let (image, grayscale) = load_png_image(&bytes, true, 0).unwrap();
+        assert!(grayscale);
+
+        for (value, pixel) in values.iter().zip(&image.pixels) {
+            assert_eq!(pixel.r, *value as f64 / 65535.0);
+            assert_eq!((pixel.g, pixel.b), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn strip_output_matches_whole_image_output() {
+        // The last strip is shorter than the others.
+        let source = gradient_png(5, 97);
+        let args = [
+            "--source-color-space",
+            "SRGB",
+            "--output-color-space",
+            "LinearSRGB",
+            "--format",
+            "RGBA16F",
+            "--header",
+        ];
+
+        let whole = run_tool("strips-whole", &source, &args).unwrap();
+        let strips = run_tool(
+            "strips",
+            &source,
+            &[&args[..], &["--strip-height", "8"]].concat(),
+        )
+        .unwrap();
+
+        let whole = RawImage::parse(&whole).unwrap();
+        let strips = RawImage::parse(&strips).unwrap();
+
+        // The timestamps differ if the two runs straddle a second boundary.
+        let header = |image: &RawImage| Header {
+            timestamp: 0,
+            ..*image.header()
+        };
+
+        assert_eq!(header(&strips), header(&whole));
+        assert!(strips.pixels() == whole.pixels(), "strip output differs");
+    }
+
+    #[test]
+    fn unpremultiplied_pixels_are_divided_by_alpha() {
+        let pixel =
+            Pixel::from_array([0.5, 0.0, 0.0, 0.5]).unpremultiply(ColorSpace::LinearSRGB, None);
+        assert_close(pixel.to_array(), [1.0, 0.0, 0.0, 0.5], 1e-12);
+
+        // Fully transparent pixels are left alone rather than divided by zero.
+        let pixel = Pixel::from_array([0.5, 0.25, 0.0, 0.0]).unpremultiply(ColorSpace::SRGB, None);
+        assert_eq!(pixel.to_array(), [0.5, 0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn images_over_the_pixel_limit_are_rejected() {
+        let source = gradient_png(16, 16);
+        let args = [
+            "--source-color-space",
+            "SRGB",
+            "--output-color-space",
+            "SRGB",
+            "--format",
+            "RGBA8",
+        ];
+
+        let error = run_tool(
+            "limit",
+            &source,
+            &[&args[..], &["--max-pixels", "255"]].concat(),
+        );
+
+        assert!(matches!(
+            error.unwrap_err().downcast_ref(),
+            Some(ConvertError::InvalidImage(_))
+        ));
+
+        let resize = ["--max-pixels", "256", "--resize", "32x32"];
+        let error = run_tool("limit-resize", &source, &[&args[..], &resize].concat());
+
+        assert!(matches!(
+            error.unwrap_err().downcast_ref(),
+            Some(ConvertError::InvalidImage(_))
+        ));
+
+        assert!(run_tool(
+            "limit-exact",
+            &source,
+            &[&args[..], &["--max-pixels", "256"]].concat()
+        )
+        .is_ok());
+        assert!(run_tool(
+            "limit-none",
+            &source,
+            &[&args[..], &["--max-pixels", "0"]].concat()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn two_color_quantization_stores_valid_indices() {
+        let indices = |image: &Image| {
+            let mut bytes = vec![];
+            store_pixels(
+                image,
+                DataFormat::R8,
+                None,
+                Endianness::Little,
+                Rounding::Round,
+                &mut bytes,
+            )
+            .unwrap();
+            bytes
+        };
+
+        let mut image = Image::new(4, 1).unwrap();
+        let (red, blue) = ([1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]);
+
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = Pixel::from_array(if i % 2 == 0 { red } else { blue });
+        }
+
+        let palette = quantize_image(&mut image, 2, 256, Rounding::Round).unwrap();
+        assert_eq!(palette.width, 2);
+
+        let stored = indices(&image);
+        assert_eq!(palette.pixels[stored[0] as usize].to_array(), red);
+        assert_eq!(palette.pixels[stored[1] as usize].to_array(), blue);
+        assert_eq!((stored[0], stored[1]), (stored[2], stored[3]));
+
+        // An image with more colors than the palette still only uses its entries.
+        let mut image = Image::new(16, 16).unwrap();
+
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            let x = i as f64 / 255.0;
+            *pixel = Pixel::from_array([x, 1.0 - x, x * x, 1.0]);
+        }
+
+        let palette = quantize_image(&mut image, 2, 256, Rounding::Round).unwrap();
+        assert!(palette.width <= 2);
+        assert!(indices(&image)
+            .iter()
+            .all(|&index| (index as u32) < palette.width));
+    }
+
+    #[test]
+    fn linear_images_are_filtered_without_conversion() {
+        let mut image = Image::new(2, 2).unwrap();
+        let pixel = Pixel::from_array([0.3, 0.6, 0.9, 1.0]);
+        image.pixels.iter_mut().for_each(|x| *x = pixel);
+
+        for &color_space in &[ColorSpace::LinearSRGB, ColorSpace::CIEXYZ] {
+            let filtered = supersample_image(&image, 1, 1, 2, color_space, None).unwrap();
+            assert_eq!(filtered.pixels[0].to_array(), pixel.to_array());
+        }
+    }
+
+    #[test]
+    fn layers_are_stored_one_after_the_other() {
+        let dir = scratch_dir("layers");
+        let output_file = dir.join("output.raw");
+
+        let first: Vec<u8> = (0..64).collect();
+        let second: Vec<u8> = (64..128).collect();
+
+        fs::write(dir.join("first.png"), rgba_png(4, 4, &first)).unwrap();
+        fs::write(dir.join("second.png"), rgba_png(4, 4, &second)).unwrap();
+
+        let args = Arguments::from_iter_safe(vec![
+            OsString::from("img2raw"),
+            dir.join("first.png").into_os_string(),
+            dir.join("second.png").into_os_string(),
+            output_file.clone().into_os_string(),
+            "--layers".into(),
+            "--header".into(),
+            "--source-color-space".into(),
+            "NonColor".into(),
+            "--output-color-space".into(),
+            "NonColor".into(),
+            "--format".into(),
+            "RGBA8".into(),
+            "--quiet".into(),
+        ])
+        .unwrap();
+
+        run(&args).unwrap();
+
+        let bytes = fs::read(&output_file).unwrap();
+        let image = RawImage::parse(&bytes).unwrap();
+
+        assert_eq!(image.header().layer_count(), 2);
+        assert_eq!(
+            image.pixels().len() as u64,
+            image.header().data_size().unwrap() * 2
+        );
+        assert_eq!(image.layer(0), Some(&first[..]));
+        assert_eq!(image.layer(1), Some(&second[..]));
+        assert_eq!(image.layer(2), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strict_fails_when_pixels_would_be_clamped() {
+        let data: Vec<u8> = [2.0f32, 0.5, 0.25, 1.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+
+        let header = HeaderBuilder::new()
+            .color_space(ColorSpace::LinearSRGB)
+            .data_format(DataFormat::RGBA32F)
+            .dimensions(1, 1)
+            .checksum(Header::compute_checksum(&data))
+            .build();
+
+        let mut source = header.as_bytes().to_vec();
+        source.extend_from_slice(&data);
+
+        let args = |format| {
+            [
+                "--transcode",
+                "--output-color-space",
+                "LinearSRGB",
+                "--format",
+                format,
+                "--strict",
+            ]
+        };
+
+        let error = run_tool("strict-rgba8", &source, &args("RGBA8")).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ConvertError::ClampedPixels { .. })
+        ));
+
+        assert!(run_tool("strict-rgba32f", &source, &args("RGBA32F")).is_ok());
+    }
+
+    #[test]
+    fn alpha_mask_replaces_the_alpha_channel() {
+        let dir = scratch_dir("alpha-mask");
+        let mask_file = dir.join("mask.png");
+
+        let mask = [0, 85, 170, 255];
+        fs::write(&mask_file, grayscale_png(png::BitDepth::Eight, &mask, 4)).unwrap();
+
+        let args = arguments(&[
+            "--transcode",
+            "--output-color-space",
+            "NonColor",
+            "--format",
+            "RGBA8",
+        ]);
+        let mut image = Image::new(4, 1).unwrap();
+
+        for pixel in &mut image.pixels {
+            *pixel = Pixel::from_array([0.25, 0.5, 0.75, 1.0]);
+        }
+
+        apply_alpha_mask(&args, &mut image, &mask_file).unwrap();
+
+        for (pixel, &alpha) in image.pixels.iter().zip(&mask) {
+            assert_eq!(pixel.to_array(), [0.25, 0.5, 0.75, alpha as f64 / 255.0]);
+        }
+
+        // The mask must have the same dimensions as the image.
+        let mut image = Image::new(2, 2).unwrap();
+        let error = apply_alpha_mask(&args, &mut image, &mask_file);
+
+        assert!(matches!(
+            error.unwrap_err().downcast_ref(),
+            Some(ConvertError::InvalidImage(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn row_alignment_override_pads_rows() {
+        let source = grayscale_png(png::BitDepth::Eight, &[10, 20, 30], 3);
+        let args = [
+            "--source-color-space",
+            "NonColor",
+            "--output-color-space",
+            "NonColor",
+            "--format",
+            "R8",
+            "--header",
+            "--row-align",
+            "256",
+        ];
+
+        let bytes = run_tool("row-align", &source, &args).unwrap();
+        let image = RawImage::parse(&bytes).unwrap();
+
+        assert_eq!(image.header().row_alignment(), Some(256));
+        assert_eq!(image.header().data_size(), Some(256));
+        assert_eq!(image.pixels().len(), 256);
+        assert_eq!(image.pixels()[..3], [10, 20, 30]);
+        assert!(image.pixels()[3..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn alpha_is_unchanged_by_srgb_conversion() {
+        let data: Vec<u8> = (0..=255)
+            .flat_map(|alpha| [alpha, 128, 255 - alpha, alpha])
+            .collect();
+        let args = [
+            "--source-color-space",
+            "LinearSRGB",
+            "--output-color-space",
+            "SRGB",
+            "--format",
+            "RGBA8",
+        ];
+
+        let bytes = run_tool("alpha", &rgba_png(256, 1, &data), &args).unwrap();
+
+        for (pixel, alpha) in bytes.chunks(4).zip(0..=255) {
+            assert_eq!(pixel[3], alpha);
+        }
+
+        // The color channels are gamma-encoded, which shows that the conversion ran.
+        assert_ne!(bytes[1], 128);
+    }
+
+    #[test]
+    fn animated_gif_loads_the_selected_frame() {
+        let mut bytes = Vec::new();
+
+        {
+            let frames = [[255, 0, 0, 255], [0, 0, 255, 255]]
+                .iter()
+                .map(|&color| image::Frame::new(RgbaImage::from_pixel(2, 2, Rgba(color))));
+
+            gif::Encoder::new(&mut bytes).encode_frames(frames).unwrap();
+        }
+
+        let expected = [[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]];
+
+        for (frame, &expected) in expected.iter().enumerate() {
+            let (image, _) = load_image(&bytes, false, frame, 0).unwrap();
+            assert_eq!((image.width, image.height), (2, 2));
+
+            for pixel in &image.pixels {
+                assert_close(pixel.to_array(), expected, 0.02);
+            }
+        }
+
+        // Only the first frame is baked unless another one is selected.
+        let args = [
+            "--source-color-space",
+            "NonColor",
+            "--output-color-space",
+            "NonColor",
+            "--format",
+            "RGBA8",
+        ];
+
+        let output = run_tool("gif", &bytes, &args).unwrap();
+        assert_eq!(output[..4], [255, 0, 0, 255]);
+
+        let selected = [&args[..], &["--frame", "1"]].concat();
+        let output = run_tool("gif-frame", &bytes, &selected).unwrap();
+        assert_eq!(output[..4], [0, 0, 255, 255]);
+
+        assert!(load_image(&bytes, false, 2, 0).is_err());
+    }
 }
@@ -0,0 +1,111 @@
+// Round-trips a synthetic image through the tool for every data format, so a
+// format whose store path disagrees with the header's size computation fails
+// here rather than in some downstream loader.
+
+use img2raw::{ColorSpace, DataFormat, Header, HeaderBuilder, RawImage};
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zerocopy::AsBytes;
+
+// Block-compressed formats need multiples of 4, and a width which is not a
+// multiple of 8 exercises the row padding of the bit-packed formats.
+const WIDTH: u32 = 12;
+const HEIGHT: u32 = 8;
+
+fn synthetic_source() -> Vec<u8> {
+    let mut pixels = Vec::new();
+
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let x = x as f32 / (WIDTH - 1) as f32;
+            let y = y as f32 / (HEIGHT - 1) as f32;
+
+            for channel in &[x, y, (x + y) / 2.0, 1.0 - x * y] {
+                pixels.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+    }
+
+    let header = HeaderBuilder::new()
+        .color_space(ColorSpace::LinearSRGB)
+        .data_format(DataFormat::RGBA32F)
+        .dimensions(WIDTH, HEIGHT)
+        .checksum(Header::compute_checksum(&pixels))
+        .build();
+
+    let mut bytes = header.as_bytes().to_vec();
+    bytes.extend_from_slice(&pixels);
+    bytes
+}
+
+fn bake(source: &Path, output: &Path, data_format: DataFormat, header: bool) -> Vec<u8> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_img2raw"));
+
+    command
+        .arg(source)
+        .arg(output)
+        .arg("--transcode")
+        .args(["--output-color-space", "LinearSRGB"])
+        .args(["--format", &data_format.to_string()])
+        .arg("--quiet");
+
+    if header {
+        command.arg("--header");
+    }
+
+    let status = command.status().expect("failed to run img2raw");
+    assert!(
+        status.success(),
+        "{}: img2raw exited with {}",
+        data_format,
+        status
+    );
+
+    fs::read(output).unwrap()
+}
+
+fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("img2raw-formats-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn output_size_matches_header_for_all_formats() {
+    let dir = scratch_dir();
+    let source = dir.join("source.raw");
+    let output = dir.join("output.raw");
+
+    fs::write(&source, synthetic_source()).unwrap();
+
+    for &data_format in DataFormat::all() {
+        let expected = HeaderBuilder::new()
+            .color_space(ColorSpace::LinearSRGB)
+            .data_format(data_format)
+            .dimensions(WIDTH, HEIGHT)
+            .build();
+
+        let total_size = expected.total_size().unwrap();
+
+        let bytes = bake(&source, &output, data_format, true);
+        assert_eq!(bytes.len() as u64, total_size, "{}", data_format);
+
+        let image = RawImage::parse(&bytes).unwrap();
+        assert_eq!(image.data_format(), data_format);
+        assert_eq!(image.color_space(), ColorSpace::LinearSRGB);
+        assert_eq!((image.width(), image.height()), (WIDTH, HEIGHT));
+
+        let bytes = bake(&source, &output, data_format, false);
+        let header_size = size_of::<Header>() as u64;
+        assert_eq!(
+            bytes.len() as u64,
+            total_size - header_size,
+            "{}",
+            data_format
+        );
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}